@@ -0,0 +1,112 @@
+use std::path::{Path, PathBuf};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use anyhow::{Result, anyhow};
+use path_absolutize::Absolutize;
+
+use crate::cli::InstallMode;
+use crate::command::InstallModes;
+use crate::link::Link;
+use crate::mapping::NameMapping;
+use crate::options::Options;
+use crate::plan::{Action, Plan};
+use crate::store::Store;
+
+/// Move existing files or directories from the target directory into `package` and link them
+/// back.
+///
+/// Each path is placed in the package where linking the package would create it, so the reverse of
+/// the name mapping (`--dotfiles` and the `rename` rules) is applied. Missing package directories
+/// are created.
+pub fn add(options: &Options, store: &mut Store, package: &Path, paths: &[PathBuf]) -> Result<()> {
+    let options = &options.for_package(package)?;
+    let plan = plan_add(options, store, package, paths)?;
+    plan.execute(options, store)?;
+    store.flush()
+}
+
+/// Compute the [Plan] for adding `paths` to `package`.
+///
+/// The package doesn't need to exist yet, if it doesn't it is recorded by its absolute path.
+/// `options` should already include the package's configuration.
+pub fn plan_add(
+    options: &Options,
+    store: &Store,
+    package: &Path,
+    paths: &[PathBuf],
+) -> Result<Plan> {
+    let package = match package.canonicalize() {
+        Ok(p) => p,
+        Err(_) => package.absolutize()?.into_owned(),
+    };
+    let target = options.target.absolutize()?.into_owned();
+
+    let mut plan = Plan {
+        package: package.to_owned(),
+        target: target.to_owned(),
+        actions: Vec::new(),
+        conflicts: Vec::new(),
+    };
+    let modes = InstallModes::new(options, &package)?;
+    let mapping = NameMapping::new(options)?;
+
+    for path in paths {
+        let path = path.absolutize()?.into_owned();
+        if !path.exists() && !path.is_symlink() {
+            return Err(anyhow!("{:?} does not exist", path));
+        }
+        if store.contains_key(&path) {
+            return Err(anyhow!("{:?} is already managed by lash", path));
+        }
+
+        let relative = match path.strip_prefix(&target) {
+            Ok(relative) if !relative.as_os_str().is_empty() => relative,
+            _ => {
+                return Err(anyhow!(
+                    "{:?} is not inside the target directory {:?}",
+                    path,
+                    target
+                ));
+            }
+        };
+        let relative = mapping.unmap(relative)?;
+
+        let source = package.join(&relative);
+        if source.exists() || source.is_symlink() {
+            return Err(anyhow!(
+                "{:?} already exists in the package, use `lash link --adopt` to replace it",
+                source
+            ));
+        }
+
+        plan.actions.push(Action::Add {
+            link: Link {
+                source: source.to_owned(),
+                target: path.to_owned(),
+                mode: modes.get(&relative, path.is_dir()),
+            },
+        });
+
+        // Record the directories leading to the path like linking the package would
+        let mut dir_source = source.to_owned();
+        for dir in path.ancestors().skip(1) {
+            if !dir_source.pop() || !dir.starts_with(&target) || dir == target {
+                break;
+            }
+            if store.contains_key(dir) {
+                continue;
+            }
+            plan.actions.push(Action::ManageDir {
+                link: Link {
+                    source: dir_source.to_owned(),
+                    target: dir.to_owned(),
+                    mode: InstallMode::Symlink,
+                },
+            });
+        }
+    }
+
+    Ok(plan)
+}
@@ -0,0 +1,216 @@
+use std::fs::{self, Metadata};
+use std::io;
+use std::os::unix::fs::{MetadataExt, lchown};
+use std::path::{Path, PathBuf};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use anyhow::{Result, anyhow};
+use filetime::FileTime;
+use path_absolutize::Absolutize;
+use walkdir::WalkDir;
+
+use crate::cli::LinkStyle;
+use crate::link::Link;
+use crate::options::Options;
+use crate::plan::{self, Plan};
+use crate::store::{Entry, EntryKind, Store};
+
+/// Check that whatever is at the target of `link` can be adopted into the package.
+///
+/// Files, directories and symlinks can be adopted. FIFOs, sockets and device nodes can't be
+/// stored in a package and a package directory is never replaced by a file.
+pub fn check(link: &Link) -> Result<()> {
+    let metadata = link.target.symlink_metadata()?;
+
+    if link.source.is_dir() {
+        return Err(anyhow!(
+            "Can't adopt {:?}, {:?} is a directory in the package",
+            link.target,
+            link.source
+        ));
+    }
+
+    if metadata.is_dir() {
+        for entry in WalkDir::new(&link.target) {
+            let entry = entry?;
+            check_type(entry.path(), &entry.path().symlink_metadata()?)?;
+        }
+        return Ok(());
+    }
+
+    check_type(&link.target, &metadata)
+}
+
+/// Check if whatever is at the target of `link` was modified more recently than the package file.
+pub fn target_is_newer(link: &Link) -> Result<bool> {
+    let target = match link.target.metadata() {
+        Ok(metadata) => metadata,
+        // Broken links
+        Err(_) => link.target.symlink_metadata()?,
+    };
+    Ok(target.modified()? > link.source.metadata()?.modified()?)
+}
+
+fn check_type(path: &Path, metadata: &Metadata) -> Result<()> {
+    let file_type = metadata.file_type();
+    match file_type.is_file() || file_type.is_dir() || file_type.is_symlink() {
+        true => Ok(()),
+        false => Err(anyhow!(
+            "Can't adopt {:?}, FIFOs, sockets and device nodes can't be adopted",
+            path
+        )),
+    }
+}
+
+/// Replace the package file with whatever is at the target of `link` and link it back.
+///
+/// - A file is copied into the package keeping its permissions, timestamps, ownership (where
+///   allowed) and extended attributes.
+/// - A symlink to a file is treated as the file it points to. Any other symlink is copied into
+///   the package as a symlink with an absolute destination so it still works from the package.
+/// - A directory is copied into the package in place of the file. The target directory is kept
+///   and each file inside it is replaced by a link, like any other directory in a package.
+pub fn adopt(plan: &Plan, options: &Options, store: &mut Store, link: &Link) -> Result<()> {
+    let entry = |source: &Path, kind| {
+        let mut entry = Entry::new(source, &plan.package, &plan.target, kind, options);
+        entry.options.adopt = true;
+        entry.with_hash()
+    };
+
+    let metadata = link.target.symlink_metadata()?;
+    if !metadata.is_dir() {
+        let kind = adopt_file(store, link, options.link_style)?;
+        store.insert(link.target.to_owned(), entry(&link.source, kind)?);
+        return Ok(());
+    }
+
+    if link.source.exists() || link.source.is_symlink() {
+        store.journal.remove_file(&link.source)?;
+    }
+
+    // Files are replaced by links as they are adopted so find everything first
+    let dir_entries = WalkDir::new(&link.target)
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for dir_entry in dir_entries.iter() {
+        let target = dir_entry.path();
+        let source = match target.strip_prefix(&link.target)? {
+            relative if relative.as_os_str().is_empty() => link.source.to_owned(),
+            relative => link.source.join(relative),
+        };
+
+        match dir_entry.file_type().is_dir() {
+            true => {
+                store.journal.create_dir_all(&source)?;
+                store.insert(target.to_owned(), entry(&source, EntryKind::Directory)?);
+            }
+            false => {
+                let file = Link {
+                    source: source.to_owned(),
+                    target: target.to_owned(),
+                    mode: link.mode,
+                };
+                let kind = adopt_file(store, &file, options.link_style)?;
+                store.insert(target.to_owned(), entry(&source, kind)?);
+            }
+        }
+    }
+
+    copy_dir_metadata(&link.target, &link.source)
+}
+
+/// Move the single file or symlink at the target of `link` into the package at its source and
+/// install it back, returning the kind of entry to record for it.
+fn adopt_file(store: &mut Store, link: &Link, style: LinkStyle) -> Result<EntryKind> {
+    let (target, source) = (link.target.as_path(), link.source.as_path());
+    if target.is_file() {
+        // Resolve any symlinks, when generating links we don't just generate an absolute path
+        // which doesn't follow symlinks
+        let existing = target.canonicalize()?;
+        store.journal.copy(&existing, source)?;
+        copy_metadata(&existing, source)?;
+    } else {
+        let dest = link_dest(target)?;
+        debug!("Adopting {:?} as a link to {:?}", target, dest);
+        if source.exists() || source.is_symlink() {
+            store.journal.remove_file(source)?;
+        }
+        store.journal.symlink(&dest, source)?;
+    }
+
+    // NOTE: Make sure to delete the target and not any potential other files pointed to by
+    // symlink
+    store.journal.remove_file(target)?;
+    plan::install(store, link, style)
+}
+
+/// Get the absolute destination of a symlink without resolving any further links.
+pub fn link_dest(link: &Path) -> Result<PathBuf> {
+    let dest = link.read_link()?;
+    let dest = match link.parent() {
+        Some(parent) if dest.is_relative() => parent.join(dest),
+        _ => dest,
+    };
+    Ok(dest.absolutize()?.into_owned())
+}
+
+/// Copy the permissions, timestamps, ownership and extended attributes of `from` to `to`.
+///
+/// Ownership and extended attributes are copied on a best effort basis, most users can't give
+/// files away or set attributes outside of the user namespace.
+pub fn copy_metadata(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = from.metadata()?;
+
+    fs::set_permissions(to, metadata.permissions())?;
+
+    if let Err(e) = lchown(to, Some(metadata.uid()), Some(metadata.gid())) {
+        debug!("Could not copy the owner of {:?} to {:?}: {}", from, to, e);
+    }
+
+    match xattr::list(from) {
+        Ok(names) => {
+            for name in names {
+                let res = match xattr::get(from, &name) {
+                    Ok(Some(value)) => xattr::set(to, &name, &value),
+                    Ok(None) => Ok(()),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = res {
+                    warn!(
+                        "Could not copy extended attribute {:?} of {:?}: {}",
+                        name, from, e
+                    );
+                }
+            }
+        }
+        Err(e) => debug!("Could not list extended attributes of {:?}: {}", from, e),
+    }
+
+    // Set the times last as changing the other metadata can update them
+    filetime::set_file_times(
+        to,
+        FileTime::from_last_access_time(&metadata),
+        FileTime::from_last_modification_time(&metadata),
+    )
+}
+
+/// Copy the metadata of each directory inside `from`, including `from` itself, to the matching
+/// directory inside `to`.
+///
+/// Adding the contents of a directory changes its timestamps so this is done once everything has
+/// been copied, starting with the innermost directories.
+pub fn copy_dir_metadata(from: &Path, to: &Path) -> Result<()> {
+    for dir_entry in WalkDir::new(from).contents_first(true) {
+        let dir_entry = dir_entry?;
+        if dir_entry.file_type().is_dir() {
+            copy_metadata(
+                dir_entry.path(),
+                &to.join(dir_entry.path().strip_prefix(from)?),
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -1,8 +1,10 @@
-use clap::{Parser, Subcommand};
+use bincode::{Decode, Encode};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde_derive::{Deserialize, Serialize};
 
 use std::path::PathBuf;
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
 #[command(arg_required_else_help = true)]
 /// This is the struct that the command line arguments are built from.
@@ -11,24 +13,78 @@ pub struct Cli {
     #[arg(long)]
     pub dotfiles: bool,
 
+    /// Link whole directories that don't exist in the target directory instead of creating them
+    /// and linking each file. They are split up again when another package adds to them
+    #[arg(long)]
+    pub fold: bool,
+
+    /// How to write the destination of the links. Links in the other style are replaced when
+    /// relinking. Defaults to absolute
+    #[arg(long, value_enum, value_name = "STYLE")]
+    pub link_style: Option<LinkStyle>,
+
+    /// How to install package files. Installed files in another mode are replaced when relinking.
+    /// Defaults to symlink
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub mode: Option<InstallMode>,
+
+    /// Don't link paths in packages matching PATTERN (gitignore syntax). Can be given more than
+    /// once
+    #[arg(long, value_name = "PATTERN")]
+    pub ignore: Vec<String>,
+
     /// Do not change any files.
     #[arg(short = 'n', long)]
     pub dry_run: bool,
 
+    /// Don't ask before adopting or overwriting files. Lash never asks when stdin isn't a terminal
+    #[arg(short, long, visible_alias = "no-confirm", global = true)]
+    pub yes: bool,
+
     /// Print more information about the files being processed.
     #[arg(short, long)]
     pub verbose: bool,
 
     /// Target directory to create links to package in. Defaults to parent of current directory
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub target: Option<PathBuf>,
 
+    /// Directory to keep the store in. Can also be set with the LASH_STORE environment variable.
+    /// Defaults to $XDG_STATE_HOME/lash/
+    #[arg(long, global = true)]
+    pub store: Option<PathBuf>,
+
+    /// Use a separate named store inside the store directory. Can also be set with the
+    /// LASH_PROFILE environment variable.
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
     #[command(subcommand)]
     /// The selected command
     pub command: Command,
 }
 
-#[derive(Subcommand, Debug)]
+/// The options of the commands that create links for what to do with paths in the way.
+#[derive(Args, Debug, Clone, Copy)]
+pub struct ConflictArgs {
+    /// "Adopt" files already existing on the file system into the package. By default this is
+    /// done by replacing the source file with the existing file, STRATEGY chooses which version to
+    /// keep. The link is still created as normal. Implies --on-conflict=adopt
+    #[arg(
+        long,
+        value_name = "STRATEGY",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "target",
+        conflicts_with = "on_conflict"
+    )]
+    pub adopt: Option<AdoptStrategy>,
+    /// What to do when a file that isn't managed by lash is in the way of a link
+    #[arg(long, value_enum)]
+    pub on_conflict: Option<ConflictPolicy>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
 pub enum Command {
     /// Install packages
     #[command(arg_required_else_help = true)]
@@ -36,10 +92,8 @@ pub enum Command {
         #[arg(name = "PACKAGES")]
         /// Packages to install
         packages: Vec<PathBuf>,
-        /// "Adopt" files already existing on the file system into the package. This is done by
-        /// replacing the source file with the existing file. The link is still created as normal.
-        #[arg(long)]
-        adopt: bool,
+        #[command(flatten)]
+        conflicts: ConflictArgs,
     },
 
     /// Remove packages
@@ -48,5 +102,209 @@ pub enum Command {
         /// Packages to remove
         #[arg(name = "PACKAGES")]
         packages: Vec<PathBuf>,
+        /// Move the files that were backed up by --on-conflict=backup back into place
+        #[arg(long)]
+        restore: bool,
+    },
+
+    /// Update installed packages, removing the links to paths that are no longer in the package
+    /// and creating links to new paths. Links that are already correct are left alone
+    #[command(alias = "restow", arg_required_else_help = true)]
+    Relink {
+        #[arg(name = "PACKAGES")]
+        /// Packages to update
+        packages: Vec<PathBuf>,
+        #[command(flatten)]
+        conflicts: ConflictArgs,
+    },
+
+    /// Move existing files or directories into a package and link them back
+    #[command(arg_required_else_help = true)]
+    Add {
+        /// Package to add the paths to
+        #[arg(short, long)]
+        package: PathBuf,
+        /// Paths inside the target directory to add
+        #[arg(name = "PATHS", required = true)]
+        paths: Vec<PathBuf>,
+    },
+
+    /// Stop managing packages or paths, replacing the links with copies of the package files
+    #[command(arg_required_else_help = true)]
+    Eject {
+        /// Packages or target paths to eject
+        #[arg(name = "PATHS")]
+        paths: Vec<PathBuf>,
+    },
+
+    /// Show how installed hard links and copies differ from the package files. Defaults to every
+    /// hard link and copy in the store
+    Diff {
+        /// Packages or target paths to compare
+        #[arg(name = "PATHS")]
+        paths: Vec<PathBuf>,
+    },
+
+    /// Copy changes made to installed hard links and copies back into the packages. Defaults to
+    /// every hard link and copy in the store
+    Pull {
+        /// Packages or target paths to pull
+        #[arg(name = "PATHS")]
+        paths: Vec<PathBuf>,
+        /// Replace the package file even if it has also changed since the file was installed
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Show whether the files in packages are linked. Exits with an error if any package is out
+    /// of sync. Defaults to every package in the store for the target directory
+    Status {
+        /// Packages to check
+        #[arg(name = "PACKAGES")]
+        packages: Vec<PathBuf>,
     },
+
+    /// List the operations recorded in the journal, newest first
+    History {
+        /// Maximum number of operations to list
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+
+    /// Reverse the changes made by an operation. Defaults to the most recent operation that
+    /// hasn't been undone
+    Undo {
+        /// ID of the operation to reverse, as shown by `lash history`
+        id: Option<u64>,
+    },
+
+    /// Inspect and edit the store of files managed by lash
+    #[command(arg_required_else_help = true)]
+    Store {
+        #[command(subcommand)]
+        command: StoreCommand,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum StoreCommand {
+    /// List the paths in the store
+    List {
+        /// Only list paths belonging to this package
+        #[arg(short, long)]
+        package: Option<PathBuf>,
+        /// Only list target paths starting with this prefix
+        #[arg(long)]
+        prefix: Option<PathBuf>,
+    },
+
+    /// Show everything recorded about a path in the store
+    #[command(arg_required_else_help = true)]
+    Show {
+        /// Target path to show
+        path: PathBuf,
+    },
+
+    /// Remove paths or whole packages from the store without changing any files
+    #[command(arg_required_else_help = true)]
+    Forget {
+        /// Target paths or packages to forget
+        #[arg(name = "PATHS")]
+        paths: Vec<PathBuf>,
+    },
+
+    /// Write the contents of the store in a readable format
+    Export {
+        /// Format to write. Defaults to the extension of OUTPUT or JSON
+        #[arg(short, long)]
+        format: Option<Format>,
+        /// File to write to. Defaults to stdout
+        output: Option<PathBuf>,
+    },
+
+    /// Read entries into the store from a file written by export
+    Import {
+        /// Format to read. Defaults to the extension of INPUT or JSON
+        #[arg(short, long)]
+        format: Option<Format>,
+        /// Replace the contents of the store instead of merging the entries into it
+        #[arg(long)]
+        replace: bool,
+        /// File to read from. Defaults to stdin
+        input: Option<PathBuf>,
+    },
+
+    /// Print the location of the store
+    Path,
+
+    /// Reconstruct the store entries for packages by scanning the target directory for links
+    /// into the packages and directories mirroring the package directories. Can be used when the
+    /// store is corrupted or missing.
+    #[command(arg_required_else_help = true)]
+    Rebuild {
+        /// Packages to look for in the target directory
+        #[arg(name = "PACKAGES")]
+        packages: Vec<PathBuf>,
+    },
+}
+
+/// The formats the store can be exported to and imported from.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Toml,
+}
+
+/// What to do when the target directory already contains something where a link or directory
+/// needs to be created.
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Report the conflicts and don't change anything in the package
+    Fail,
+    /// Report the conflicts and leave the conflicting paths alone
+    Skip,
+    /// Move the conflicting path out of the way and create the link
+    Backup,
+    /// Remove the conflicting path and create the link
+    Overwrite,
+    /// Replace the package file with the conflicting file and create the link
+    Adopt,
+}
+
+/// How the destination of the symlinks that are created is written.
+#[derive(ValueEnum, Encode, Decode, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkStyle {
+    /// The absolute path to the package file
+    Absolute,
+    /// The path to the package file relative to the directory containing the link
+    Relative,
+}
+
+/// How package files are installed into the target directory.
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InstallMode {
+    /// A symbolic link to the package file
+    #[default]
+    Symlink,
+    /// A hard link to the package file. The target must be on the same file system
+    Hardlink,
+    /// A copy of the package file. Copies that have been changed are never removed
+    Copy,
+}
+
+/// Which version of a file to keep when adopting it.
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AdoptStrategy {
+    /// Replace the package file with the file in the target directory
+    Target,
+    /// Keep the package file and move the file in the target directory to the backup area
+    Package,
+    /// Keep whichever file was modified most recently
+    Newer,
+    /// Ask which file to keep. Keeps the newer file when lash can't ask
+    Ask,
 }
@@ -1,148 +1,750 @@
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::fs::{copy, create_dir_all, remove_dir, remove_file};
-use std::os::unix::fs::symlink;
-use std::path::{Path, PathBuf};
+use std::iter::once;
+use std::path::{Component, Path, PathBuf};
 
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
 
 use anyhow::{Result, anyhow};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use path_absolutize::Absolutize;
 use walkdir::WalkDir;
 
-use crate::link::Link;
+use crate::adopt;
+use crate::cli::{AdoptStrategy, ConflictPolicy, InstallMode};
+use crate::confirm::Confirm;
+use crate::hooks::{self, Hook};
+use crate::link::{Link, has_style};
+use crate::mapping::NameMapping;
+use crate::options::BACKUP_DIR;
 use crate::options::{Command, Options};
+use crate::plan::{Action, Conflict, ConflictKind, Plan};
+use crate::store::{Entry, EntryKind, Store, now, same_file};
 
-/// Performs the actions for a given [Link] when uninstalling a package.
+/// Builds up the [Plan] for a package.
 ///
-/// The actions taken vary depending on if the [Link] target is a directory or a symlink.
-fn do_unlink(options: &Options, link: &Link, store: &mut HashMap<PathBuf, PathBuf>) -> Result<()> {
-    if link.target.is_dir() {
-        if link.target.read_dir()?.next().is_none() {
-            info!("Directory {:?} is empty, removing...", link.target);
-            if !options.dry_run {
-                let res = remove_dir(link.target.as_path());
-                store.remove(&link.target);
-                debug!("remove_dir result {:?}", res);
+/// Tracks the paths that the planned actions will remove so later decisions see the file system
+/// as it will be when the plan is executed rather than as it is now. This keeps the plan
+/// identical whether or not it is going to be executed.
+struct Planner<'a> {
+    options: &'a Options,
+    store: &'a Store,
+    plan: Plan,
+    /// Paths that will no longer exist once the actions planned so far have been performed
+    removed: HashSet<PathBuf>,
+    /// Conflicting paths that are being left alone and folded directories, nothing inside them is
+    /// linked
+    skipped: HashSet<PathBuf>,
+    /// Directories that can be linked as a whole if they don't exist yet
+    foldable: HashSet<PathBuf>,
+    /// Directories that will be folded directories once the actions planned so far have been
+    /// performed, and the package directory they link to
+    folds: HashMap<PathBuf, PathBuf>,
+    /// When planning started, used to name the backups
+    timestamp: u64,
+}
+
+impl<'a> Planner<'a> {
+    fn new(options: &'a Options, store: &'a Store, package: PathBuf, target: PathBuf) -> Self {
+        Self {
+            options,
+            store,
+            plan: Plan {
+                package,
+                target,
+                actions: Vec::new(),
+                conflicts: Vec::new(),
+            },
+            removed: HashSet::new(),
+            skipped: HashSet::new(),
+            foldable: HashSet::new(),
+            folds: HashMap::new(),
+            timestamp: now(),
+        }
+    }
+
+    fn push(&mut self, action: Action) {
+        match &action {
+            Action::RemoveLink { target, .. }
+            | Action::RemoveCopy { target, .. }
+            | Action::RemoveDir { target }
+            | Action::Overwrite { target, .. } => {
+                self.removed.insert(target.to_owned());
+            }
+            Action::Restore { target, .. } | Action::OfferRestore { target, .. } => {
+                self.removed.remove(target);
             }
+            Action::CreateDir { link }
+            | Action::CreateLink { link }
+            | Action::Backup { link, .. }
+            | Action::Choose { link, .. } => {
+                self.removed.remove(&link.target);
+            }
+            Action::Refold { target, .. } => {
+                // Everything left in the directory is replaced by a single link
+                if let Ok(dir) = target.read_dir() {
+                    self.removed
+                        .extend(dir.filter_map(|e| e.ok()).map(|e| e.path()));
+                }
+            }
+            _ => {}
         }
-    } else if link.target.is_symlink() && link.target.read_link()? == link.source {
-        info!("Removing link: {:?} -> {:?}", link.target, link.source);
-        if !options.dry_run {
-            let res = remove_file(link.target.as_path());
-            store.remove(&link.target);
-            debug!("remove_file result {:?}", res);
+        self.plan.actions.push(action);
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        !self.removed.contains(path) && path.exists()
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        !self.removed.contains(path) && path.is_symlink()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        !self.removed.contains(path) && path.is_dir()
+    }
+
+    /// Check if a directory will be empty once the actions planned so far have been performed.
+    fn is_empty_dir(&self, path: &Path) -> Result<bool> {
+        for entry in path.read_dir()? {
+            if !self.removed.contains(&entry?.path()) {
+                return Ok(false);
+            }
         }
+        Ok(true)
     }
-    Ok(())
-}
 
-/// Performs the actions for a given [Link] when installing a package.
-///
-/// The actions taken vary depending on if the [Link] source is a directory or if the target exists
-/// or is a symlink.
-fn do_link(options: &Options, link: &Link, store: &mut HashMap<PathBuf, PathBuf>) -> Result<()> {
-    if link.source.is_dir() {
-        debug!("Checking required directory exists {:?}", link.target);
-        if link.target.exists() {
-            if !options.dry_run {
-                // Mark it as managed
-                store.insert(link.target.to_owned(), link.source.to_owned());
+    /// Plans the actions for a given [Link] when uninstalling a package.
+    ///
+    /// The actions taken vary depending on if the [Link] target is a directory or a symlink.
+    /// Paths inside a folded directory are inside the package so are left alone, the folded
+    /// directory is removed like any other link.
+    fn unlink(&mut self, link: &Link) -> Result<()> {
+        if self.store.in_fold(&link.target) {
+            return Ok(());
+        }
+
+        if let Some(entry) = self.store.get(&link.target)
+            && matches!(entry.kind, EntryKind::Hardlink | EntryKind::Copy)
+            && entry.source == link.source
+            && self.exists(&link.target)
+        {
+            return self.remove_copy(&link.target, &entry.to_owned());
+        }
+
+        if self.is_symlink(&link.target) && resolve_link(&link.target)? == link.source {
+            self.push(Action::RemoveLink {
+                target: link.target.to_owned(),
+                source: link.source.to_owned(),
+            });
+            self.restore(&link.target);
+        } else if self.is_dir(&link.target) {
+            if self.is_empty_dir(&link.target)? {
+                debug!("Directory {:?} is empty", link.target);
+                self.push(Action::RemoveDir {
+                    target: link.target.to_owned(),
+                });
+                self.restore(&link.target);
+            } else if self.options.fold
+                && let Some(source) = self.refold_source(&link.target)?
+            {
+                self.folds.insert(link.target.to_owned(), source.to_owned());
+                self.push(Action::Refold {
+                    target: link.target.to_owned(),
+                    source,
+                });
             }
+        }
+        Ok(())
+    }
+
+    /// Plans removing the hard link or copy at `target` recorded by `entry`, unless it has been
+    /// changed since it was installed. Changed files are left in place and forgotten.
+    fn remove_copy(&mut self, target: &Path, entry: &Entry) -> Result<()> {
+        if self.is_symlink(target) || entry.is_modified(target)? {
+            warn!(
+                "{:?} has been changed since it was installed from {:?}, leaving it in place",
+                target, entry.source
+            );
+            self.push(Action::Forget {
+                target: target.to_owned(),
+            });
             return Ok(());
         }
 
-        // Create directory
-        debug!("Creating missing directory {:?}", link.target);
-        if !options.dry_run {
-            match create_dir_all(link.target.as_path()) {
-                Ok(..) => {
-                    store.insert(link.target.to_owned(), link.source.to_owned());
+        self.push(Action::RemoveCopy {
+            target: target.to_owned(),
+            source: entry.source.to_owned(),
+        });
+        self.restore(target);
+        Ok(())
+    }
+
+    /// Find the package directory that a directory can be folded back into once the actions
+    /// planned so far have been performed.
+    ///
+    /// That is only the case when everything left in the directory is a link created by lash to
+    /// the same package directory and every path in that package directory is linked.
+    fn refold_source(&self, dir: &Path) -> Result<Option<PathBuf>> {
+        let mut source_dir: Option<PathBuf> = None;
+        let mut count = 0;
+
+        for dir_entry in dir.read_dir()? {
+            let path = dir_entry?.path();
+            if self.removed.contains(&path) {
+                continue;
+            }
+            count += 1;
+
+            let source = match self.folds.get(&path) {
+                Some(source) => source.to_owned(),
+                None => match self.store.get(&path) {
+                    Some(entry)
+                        if entry.kind != EntryKind::Directory
+                            && path.is_symlink()
+                            && resolve_link(&path)? == entry.source =>
+                    {
+                        entry.source.to_owned()
+                    }
+                    _ => return Ok(None),
+                },
+            };
+
+            let parent = match source.parent() {
+                Some(parent) if source.file_name() == path.file_name() => parent,
+                _ => return Ok(None),
+            };
+            match &source_dir {
+                None => source_dir = Some(parent.to_owned()),
+                Some(source_dir) if source_dir == parent => {}
+                Some(_) => return Ok(None),
+            }
+        }
+
+        let Some(source) = source_dir else {
+            return Ok(None);
+        };
+        match source.read_dir()?.count() == count {
+            true => Ok(Some(source)),
+            false => Ok(None),
+        }
+    }
+
+    /// Find the package directory a directory is folded into, if it is a folded directory or will
+    /// be one once the actions planned so far have been performed.
+    fn fold_source(&self, dir: &Path) -> Option<PathBuf> {
+        if let Some(source) = self.folds.get(dir) {
+            return Some(source.to_owned());
+        }
+
+        match self.store.get(dir) {
+            Some(entry) if entry.kind == EntryKind::Folded && self.is_symlink(dir) => {
+                Some(entry.source.to_owned())
+            }
+            _ => None,
+        }
+    }
+
+    /// Plans moving the path displaced by the entry for `target` back from the backup area, if
+    /// there is one and restoring was asked for. Otherwise the user is asked whether to restore it
+    /// when possible.
+    fn restore(&mut self, target: &Path) {
+        let Some(backup) = self.store.get(target).and_then(|e| e.backup.to_owned()) else {
+            return;
+        };
+
+        if !backup.exists() && !backup.is_symlink() {
+            warn!("Backup {:?} of {:?} no longer exists", backup, target);
+            return;
+        }
+
+        match self.options.restore {
+            true => self.push(Action::Restore {
+                target: target.to_owned(),
+                backup,
+            }),
+            false if Confirm::can_ask(self.options) => self.push(Action::OfferRestore {
+                target: target.to_owned(),
+                backup,
+            }),
+            false => info!(
+                "{:?} was backed up to {:?}, use --restore to restore it",
+                target, backup
+            ),
+        }
+    }
+
+    /// Plans the actions for a given [Link] when installing a package.
+    ///
+    /// The actions taken vary depending on if the [Link] source is a directory or if the target
+    /// exists or is a symlink.
+    fn link(&mut self, link: &Link) -> Result<()> {
+        if self.skipped.iter().any(|p| link.target.starts_with(p)) {
+            debug!("Skipping {:?} inside a conflicting path", link.target);
+            return Ok(());
+        }
+
+        if link.source.is_dir() {
+            debug!("Checking required directory exists {:?}", link.target);
+            if self.is_symlink(&link.target)
+                && link.target.canonicalize().ok() == Some(link.source.clone())
+            {
+                debug!("Directory {:?} is already folded", link.target);
+                self.skipped.insert(link.target.to_owned());
+                return self.keep_link(link);
+            }
+            if let Some(source) = self.fold_source(&link.target) {
+                // Another package's directory is linked here, split it into links to each of its
+                // paths so this package can add to it
+                for dir_entry in source.read_dir()? {
+                    let path = dir_entry?.path();
+                    if path.is_dir() {
+                        let name = path.file_name().unwrap_or_default();
+                        self.folds.insert(link.target.join(name), path);
+                    }
                 }
-                Err(_) => {
-                    error!("Failed to create directory {:?}", link.target);
-                    // TODO: Should this return an error to abort or should it continue to
-                    // link as many files as possible?
+                self.push(Action::Unfold {
+                    target: link.target.to_owned(),
+                    source,
+                });
+                self.push(Action::ManageDir { link: link.clone() });
+                return Ok(());
+            }
+            if self.is_dir(&link.target) {
+                self.push(Action::ManageDir { link: link.clone() });
+                return Ok(());
+            }
+            if (self.exists(&link.target) || self.is_symlink(&link.target))
+                && !self.conflict(link)?
+            {
+                // A backup clears the target and creates the directory, so its contents are
+                // still linked
+                let backup = matches!(
+                    self.plan.actions.last(),
+                    Some(Action::Backup { link: l, .. }) if l.target == link.target
+                );
+                if !backup {
+                    self.skipped.insert(link.target.to_owned());
                 }
+                return Ok(());
+            }
+            if self.foldable.contains(&link.target) {
+                // Nothing is here yet so link the whole directory
+                self.skipped.insert(link.target.to_owned());
+                self.push(Action::CreateLink { link: link.clone() });
+                return Ok(());
             }
+            self.push(Action::CreateDir { link: link.clone() });
+            return Ok(());
         }
-        return Ok(());
+        // Source not a directory
+
+        debug!("Processing link: {:?} -> {:?}", link.target, link.source);
+        // Simple case first where no link or file exists at the target
+        if !self.exists(&link.target) && !self.is_symlink(&link.target) {
+            self.push(Action::CreateLink { link: link.clone() });
+            return Ok(());
+        }
+
+        // A hard link or copy installed earlier
+        if let Some(entry) = self.store.get(&link.target)
+            && matches!(entry.kind, EntryKind::Hardlink | EntryKind::Copy)
+            && entry.source == link.source
+            && !self.is_symlink(&link.target)
+        {
+            if entry.is_modified(&link.target)? {
+                warn!(
+                    "{:?} has been changed since it was installed from {:?}, leaving it in place",
+                    link.target, link.source
+                );
+                return Ok(());
+            }
+            match entry.is_current(&link.target, link.mode)? {
+                true => self.push(Action::KeepLink { link: link.clone() }),
+                false => self.push(Action::Relink { link: link.clone() }),
+            }
+            return Ok(());
+        }
+
+        // Link exists and points to the right file
+        if self.is_symlink(&link.target)
+            && link.target.canonicalize().ok() == Some(link.source.clone())
+        {
+            return self.keep_link(link);
+        }
+
+        // Already a hard link to the right file
+        if link.mode == InstallMode::Hardlink
+            && !self.is_symlink(&link.target)
+            && same_file(&link.target, &link.source)
+        {
+            self.push(Action::KeepLink { link: link.clone() });
+            return Ok(());
+        }
+
+        // Something else is in the way
+        if self.conflict(link)? {
+            self.push(Action::CreateLink { link: link.clone() });
+        }
+        Ok(())
     }
-    // Source not a directory
 
-    info!("Processing link: {:?} -> {:?}", link.target, link.source);
-    // Simple case first where no link or file exists at the target
-    if !link.target.exists() && !link.target.is_symlink() {
-        debug!("Making link {:?} -> {:?}", link.target, link.source);
-        if !options.dry_run {
-            let res = symlink(link.source.as_path(), link.target.as_path());
-            debug!("symlink result {:?}", res);
-            // TODO: Match block here
-            store.insert(link.target.to_owned(), link.source.to_owned());
+    /// Plans keeping a link that already points to the right file (or directory), remaking it if
+    /// it's written in the other link style or the file should be installed in another mode.
+    fn keep_link(&mut self, link: &Link) -> Result<()> {
+        if link.mode != InstallMode::Symlink && !link.source.is_dir() {
+            self.push(Action::Relink { link: link.clone() });
+            return Ok(());
         }
-        info!("Created link {:?} -> {:?}", link.target, link.source);
-        return Ok(());
+        match has_style(&link.target.read_link()?, self.options.link_style)? {
+            true => self.push(Action::KeepLink { link: link.clone() }),
+            false => self.push(Action::Relink { link: link.clone() }),
+        }
+        Ok(())
+    }
+
+    /// Records a conflict with whatever is at the target of `link` and plans how to resolve it
+    /// according to the conflict policy.
+    ///
+    /// Returns whether the target has been cleared so the link (or directory) can be created.
+    fn conflict(&mut self, link: &Link) -> Result<bool> {
+        let metadata = link.target.symlink_metadata()?;
+        let kind = if metadata.is_symlink() {
+            ConflictKind::Symlink {
+                dest: link.target.read_link()?,
+            }
+        } else if metadata.is_dir() {
+            ConflictKind::Directory
+        } else if metadata.is_file() {
+            ConflictKind::File
+        } else {
+            ConflictKind::Special
+        };
+
+        self.plan.conflicts.push(Conflict {
+            link: link.clone(),
+            kind: kind.clone(),
+        });
+
+        match self.options.on_conflict {
+            ConflictPolicy::Fail | ConflictPolicy::Skip => Ok(false),
+            ConflictPolicy::Backup => {
+                self.push(Action::Backup {
+                    link: link.clone(),
+                    backup: self.backup_path(&link.target),
+                });
+                Ok(false)
+            }
+            ConflictPolicy::Overwrite => match kind {
+                ConflictKind::Directory | ConflictKind::Special => Err(anyhow!(
+                    "Refusing to overwrite {:?}, only files and links can be overwritten",
+                    link.target
+                )),
+                _ => {
+                    self.push(Action::Overwrite {
+                        target: link.target.to_owned(),
+                        source: link.source.to_owned(),
+                    });
+                    Ok(true)
+                }
+            },
+            ConflictPolicy::Adopt => {
+                let backup = self.backup_path(&link.target);
+                let action = match self.options.adopt {
+                    AdoptStrategy::Target => Action::Adopt { link: link.clone() },
+                    AdoptStrategy::Package => Action::Backup {
+                        link: link.clone(),
+                        backup,
+                    },
+                    AdoptStrategy::Newer => Action::keep_newer(link, &backup)?,
+                    AdoptStrategy::Ask => Action::Choose {
+                        link: link.clone(),
+                        backup,
+                    },
+                };
+                if !matches!(action, Action::Backup { .. }) {
+                    adopt::check(link)?;
+                }
+                self.push(action);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Get the path in the backup area that a conflicting path is moved to.
+    ///
+    /// Backups are kept next to the store in a directory for the time lash was run, mirroring the
+    /// absolute path of the target.
+    fn backup_path(&self, target: &Path) -> PathBuf {
+        let relative: PathBuf = target
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+        let backup = self
+            .options
+            .store
+            .with_file_name(BACKUP_DIR)
+            .join(self.timestamp.to_string())
+            .join(relative);
+
+        // Never replace an earlier backup
+        let mut unique = backup.to_owned();
+        let mut n = 1;
+        while unique.exists() || unique.is_symlink() {
+            let mut name = backup.as_os_str().to_owned();
+            name.push(format!(".{}", n));
+            unique = name.into();
+            n += 1;
+        }
+        unique
     }
 
-    // Link exists and points to the right file
-    if link.target.is_symlink() && link.target.canonicalize()? == link.source {
-        debug!("Link {:?} already exists!", link.target);
+    /// Plans the removal of links into the package whose files no longer exist ("zombies") and
+    /// of the managed directories that are left empty by removing them.
+    fn zombies(&mut self) -> Result<()> {
+        let mut clean_dirh: HashSet<PathBuf> = HashSet::new();
+        let mut clean_dirq: VecDeque<PathBuf> = VecDeque::new();
 
-        if !options.dry_run {
-            store.insert(link.target.to_owned(), link.source.to_owned());
+        info!(
+            "Checking destination for dangling links to package {:?}",
+            self.plan.package
+        );
+
+        let package = self.plan.package.to_owned();
+        let mut entries: Vec<(PathBuf, Entry)> = self
+            .store
+            .iter()
+            .filter(|(t, e)| e.belongs_to(t, &package, &self.plan.target))
+            .map(|(t, e)| (t.to_owned(), e.to_owned()))
+            .collect();
+        // Keep the plan stable between runs
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (target, entry) in entries.iter() {
+            debug!("Store entry found for this package+target: {:?}", entry);
+            match target.try_exists() {
+                Ok(false) => {
+                    if !target.is_symlink() {
+                        // file doesn't exist, somehow store is out of sync
+                        self.push(Action::Forget {
+                            target: target.to_owned(),
+                        });
+                        continue;
+                    }
+
+                    // broken symbolic link, relative links are resolved from the link's directory
+                    let link_dest = match adopt::link_dest(target) {
+                        Err(e) => {
+                            error!("Could not get link destination for {:?}", target);
+                            return Err(e);
+                        }
+                        Ok(p) => p,
+                    };
 
-            // Remake the link if it's a relative link and not absolute
-            // NOTE: Need to compare the str version because Paths will automatically resolve
-            // the relative paths to make them equivalent.
-            if link.target.read_link()?.as_os_str()
-                != link.target.read_link()?.absolutize()?.as_os_str()
+                    if link_dest.starts_with(&package) && !link_dest.exists() {
+                        debug!("Found zombie link {:?}", target);
+                        if let Some(parent) = target.parent()
+                            && !clean_dirh.contains(parent)
+                        {
+                            clean_dirq.push_back(parent.to_path_buf());
+                            clean_dirh.insert(parent.to_path_buf());
+                        }
+                        self.push(Action::RemoveLink {
+                            target: target.to_owned(),
+                            source: link_dest,
+                        });
+                    }
+                }
+                Ok(true)
+                    if matches!(entry.kind, EntryKind::Hardlink | EntryKind::Copy)
+                        && !entry.source.exists() =>
+                {
+                    debug!("Found zombie copy {:?}", target);
+                    if let Some(parent) = target.parent()
+                        && !clean_dirh.contains(parent)
+                    {
+                        clean_dirq.push_back(parent.to_path_buf());
+                        clean_dirh.insert(parent.to_path_buf());
+                    }
+                    self.remove_copy(target, entry)?;
+                }
+                Ok(true) => { // dir/target exists so nothing to do
+                }
+                Err(e) => {
+                    error!("Could not check if {:?} exists.", target);
+                    return Err(e.into());
+                }
+            }
+        }
+
+        while let Some(entry) = clean_dirq.pop_front() {
+            if self.store.contains_key(&entry)
+                && self.is_dir(&entry)
+                && self.is_empty_dir(&entry)?
             {
-                // TODO: Proper error handling
-                let res = remove_file(link.target.as_path());
-                debug!("remove result {:?}", res);
-                let res = symlink(link.source.as_path(), link.target.as_path());
-                debug!("symlink result {:?}", res);
+                debug!("Found zombie dir {:?}", &entry);
+                self.push(Action::RemoveDir {
+                    target: entry.to_owned(),
+                });
+                if let Some(parent) = entry.parent()
+                    && !clean_dirh.contains(parent)
+                {
+                    clean_dirq.push_back(parent.to_path_buf());
+                    clean_dirh.insert(parent.to_path_buf());
+                }
             }
         }
-        return Ok(());
+
+        Ok(())
     }
 
-    if options.adopt {
-        // TODO: Add a confirm/noconfirm option and a y/n prompt
-        info!("Found a file at {:?}, adopting...", link.target);
+    /// Plans the removal of the paths recorded in the store for the package that no longer match
+    /// any of its `links`, e.g. after the package has been reorganised or the name mapping
+    /// changed. Links that are still correct are left alone.
+    fn stale(&mut self, links: &[Link]) -> Result<()> {
+        let current: HashSet<&Path> = links.iter().map(|l| l.target.as_path()).collect();
 
-        // Resolve any symlinks, when generating links we don't just generate an absolute
-        // path which doesn't follow symlinks
-        let target = link.target.canonicalize()?;
+        let package = self.plan.package.to_owned();
+        let mut entries: Vec<(PathBuf, Entry)> = self
+            .store
+            .iter()
+            .filter(|(t, e)| e.belongs_to(t, &package, &self.plan.target))
+            .filter(|(t, _)| !current.contains(t.as_path()))
+            .map(|(t, e)| (t.to_owned(), e.to_owned()))
+            .collect();
+        // Reverse order so the contents of a directory are removed before the directory
+        entries.sort_by(|a, b| b.0.cmp(&a.0));
 
-        if !options.dry_run {
-            // TODO: Better error handling
-            match copy(&target, link.source.as_path()) {
-                Ok(..) => {
-                    // NOTE: Make sure to delete the target and not any potential other files pointed
-                    // to by symlink
-                    let res = remove_file(link.target.as_path());
-                    debug!("remove_file result {:?}", res);
+        for (target, entry) in entries {
+            if !self.exists(&target) && !self.is_symlink(&target) {
+                // Already handled when cleaning up zombies
+                continue;
+            }
+
+            debug!("Found stale {} {:?}", entry.kind, target);
+            match entry.kind {
+                EntryKind::Link | EntryKind::Folded => {
+                    if self.is_symlink(&target) && resolve_link(&target)? == entry.source {
+                        self.push(Action::RemoveLink {
+                            target,
+                            source: entry.source,
+                        });
+                    } else {
+                        warn!(
+                            "{:?} is no longer a link to {:?}, leaving it in place",
+                            target, entry.source
+                        );
+                        self.push(Action::Forget { target });
+                    }
                 }
-                Err(_e) => {
-                    error!("Failed to adopt file {:?}", target)
+                EntryKind::Hardlink | EntryKind::Copy => self.remove_copy(&target, &entry)?,
+                EntryKind::Directory => {
+                    if self.is_dir(&target) && self.is_empty_dir(&target)? {
+                        self.push(Action::RemoveDir { target });
+                    }
                 }
             }
         }
 
-        debug!("Making link {:?} -> {:?}", link.target, link.source);
+        Ok(())
+    }
+}
+
+/// What to do with the packages being processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Install the package
+    Link,
+    /// Remove the package
+    Unlink,
+    /// Bring an installed package up to date, removing the paths that are no longer part of it
+    Relink,
+}
 
-        if !options.dry_run {
-            // TODO: Better error handling
-            let res = symlink(link.source.as_path(), link.target.as_path());
-            debug!("symlink result {:?}", res);
-            store.insert(link.target.to_owned(), link.source.to_owned());
-            return Ok(());
+/// Compute the [Plan] for installing, uninstalling or relinking `package` into `target`.
+///
+/// Links into the package whose files no longer exist are cleaned up first, then each file and
+/// directory inside the package is processed. When relinking, the store entries for the package
+/// that no longer match a path in the package are removed before the package is linked.
+pub fn plan_package(
+    options: &Options,
+    package: &Path,
+    target: &Path,
+    mode: Mode,
+    store: &Store,
+) -> Result<Plan> {
+    let links = get_paths(options, package, target, mode == Mode::Unlink)?;
+
+    // Store entries record the canonical package and absolute target so the same package is
+    // recognised however it was specified on the command line
+    let canonical_package = match package.canonicalize() {
+        Err(e) => {
+            error!("Could not get canonicalized path of {:?}.", package);
+            return Err(e.into());
+        }
+        Ok(p) => p,
+    };
+    let absolute_target = match target.absolutize() {
+        Err(e) => {
+            error!(
+                "Could not get absolute path of {:?}. Does the current directory exist?",
+                target
+            );
+            return Err(e.into());
         }
+        Ok(p) => p.into_owned(),
+    };
+
+    let mut planner = Planner::new(options, store, canonical_package, absolute_target);
+    if options.fold && mode != Mode::Unlink {
+        planner.foldable = foldable(&links)?;
     }
 
-    // File exists but is not a link to package and we're not adopting so ignore
-    Ok(())
+    planner.zombies()?;
+
+    if mode == Mode::Relink {
+        planner.stale(&links)?;
+    }
+
+    for link in links {
+        match mode {
+            Mode::Link | Mode::Relink => planner.link(&link)?,
+            Mode::Unlink => planner.unlink(&link)?,
+        }
+    }
+
+    Ok(planner.plan)
+}
+
+/// Find the directories in `links` that can be folded into a single link to the package
+/// directory. Those are the directories where linking everything inside them would recreate the
+/// package directory exactly, so not ones with ignored or renamed paths or hard links and copies
+/// inside them.
+fn foldable(links: &[Link]) -> Result<HashSet<PathBuf>> {
+    let targets: HashMap<&Path, &Path> = links
+        .iter()
+        .filter(|l| l.mode == InstallMode::Symlink)
+        .map(|l| (l.source.as_path(), l.target.as_path()))
+        .collect();
+    let mut foldable = HashSet::new();
+
+    'links: for link in links
+        .iter()
+        .filter(|l| l.source.is_dir() && l.mode == InstallMode::Symlink)
+    {
+        for dir_entry in WalkDir::new(&link.source).min_depth(1) {
+            let dir_entry = dir_entry?;
+            let expected = link
+                .target
+                .join(dir_entry.path().strip_prefix(&link.source)?);
+            if targets.get(dir_entry.path()) != Some(&expected.as_path()) {
+                continue 'links;
+            }
+        }
+        foldable.insert(link.target.to_owned());
+    }
+
+    Ok(foldable)
 }
 
 fn package_error<E>(package: &Path, err: E) -> (PathBuf, anyhow::Error)
@@ -154,13 +756,21 @@ where
 
 pub fn process_packages(
     options: &Options,
-    store: &mut HashMap<PathBuf, PathBuf>,
+    store: &mut Store,
 ) -> Vec<core::result::Result<PathBuf, (PathBuf, anyhow::Error)>> {
+    let mut confirm = Confirm::new(options);
+
     options
         .packages
         .iter()
         .map(|package| {
-            info!("Processing package {:?}", package);
+            let options = &options
+                .for_package(package)
+                .map_err(|err| package_error(package, err))?;
+            match &options.description {
+                Some(description) => info!("Processing package {:?}: {}", package, description),
+                None => info!("Processing package {:?}", package),
+            }
 
             // Perform shell expansion on destination name/path
             let target: PathBuf = shellexpand::full(
@@ -174,25 +784,40 @@ pub fn process_packages(
             .into_owned()
             .into();
 
-            let uninstall = match options.command {
-                Command::Link => false,
-                Command::Unlink => true,
+            let mode = match options.command {
+                Command::Link => Mode::Link,
+                Command::Unlink => Mode::Unlink,
+                Command::Relink => Mode::Relink,
+                _ => {
+                    return Err(package_error(
+                        package,
+                        anyhow!("{:?} does not process packages", options.command),
+                    ));
+                }
             };
 
-            let links = get_paths(package, &target, options.dotfiles, uninstall)
+            let mut plan = plan_package(options, package, &target, mode, store)
+                .map_err(|err| package_error(package, err))?;
+
+            plan.check_conflicts(options.on_conflict)
                 .map_err(|err| package_error(package, err))?;
 
-            let f = match options.command {
-                Command::Link => do_link,
-                Command::Unlink => do_unlink,
+            plan.confirm(&mut confirm)
+                .map_err(|err| package_error(package, err))?;
+
+            let (pre_hook, post_hook) = match mode {
+                Mode::Link | Mode::Relink => (Hook::PreLink, Hook::PostLink),
+                Mode::Unlink => (Hook::PreUnlink, Hook::PostUnlink),
             };
 
-            check_zombies(package, &target, options, store)
+            hooks::run(options, pre_hook, &plan).map_err(|err| package_error(package, err))?;
+
+            plan.execute(options, store)
                 .map_err(|err| package_error(package, err))?;
 
-            for link in links {
-                f(options, &link, store).map_err(|err| package_error(package, err))?;
-            }
+            store.flush().map_err(|err| package_error(package, err))?;
+
+            hooks::run(options, post_hook, &plan).map_err(|err| package_error(package, err))?;
 
             debug!("Done processing package {:?}", package);
             Ok(package.to_owned())
@@ -200,17 +825,210 @@ pub fn process_packages(
         .collect()
 }
 
-/// Convert "dot-" in a [`Path`] to "."
-fn map_path_dots<P>(path: P) -> Result<PathBuf>
-where
-    P: AsRef<Path>,
-{
-    let str = path
-        .as_ref()
-        .to_str()
-        .ok_or(anyhow!("Could not convert path to str"))?;
-    let path = str.replace("dot-", ".");
-    Ok(PathBuf::from(path))
+/// Reconstruct the store entries for `package` by scanning the target directory.
+///
+/// Every symlink in the target directory that resolves into the package is recorded as a link
+/// (including links to files that no longer exist so they are cleaned up as zombies later) and
+/// every directory in the target that mirrors a directory inside the package is recorded as a
+/// directory. Hard links and copies can't be told apart from files lash didn't create so they
+/// aren't found. Any existing entries for the package in the target directory are replaced.
+pub fn rebuild_package(options: &Options, package: &Path, store: &mut Store) -> Result<()> {
+    let options = &options.for_package(package)?;
+    let canonical_package = package.canonicalize()?;
+    let absolute_target = options.target.absolutize()?.into_owned();
+
+    let mut found: HashMap<PathBuf, Entry> = HashMap::new();
+
+    // Directories don't point back to the package so they have to be found from the package side
+    for link in get_paths(options, package, &absolute_target, false)? {
+        if link.source.is_dir() && link.target.is_dir() && !link.target.is_symlink() {
+            let entry = Entry::new(
+                &link.source,
+                &canonical_package,
+                &absolute_target,
+                EntryKind::Directory,
+                options,
+            );
+            found.insert(link.target, entry);
+        }
+    }
+
+    // Subtrees of the package can be linked outside of the target directory
+    let roots = once(&absolute_target).chain(options.targets.values());
+    for res in roots.flat_map(|root| WalkDir::new(root).min_depth(1)) {
+        let dir_entry = match res {
+            Ok(e) => e,
+            Err(e) => {
+                warn!("Skipping {:?}: {}", e.path(), e);
+                continue;
+            }
+        };
+        if !dir_entry.path_is_symlink() {
+            continue;
+        }
+
+        let source = resolve_link(dir_entry.path())?;
+        if !source.starts_with(&canonical_package) {
+            continue;
+        }
+
+        let kind = match source.is_dir() {
+            true => EntryKind::Folded,
+            false => EntryKind::Link,
+        };
+        let entry = Entry::new(&source, &canonical_package, &absolute_target, kind, options);
+        found.insert(dir_entry.into_path(), entry);
+    }
+
+    // The package's own directories are found through folded directories
+    let in_fold: Vec<PathBuf> = found
+        .keys()
+        .filter(|t| {
+            t.ancestors()
+                .skip(1)
+                .any(|dir| found.get(dir).is_some_and(|e| e.kind == EntryKind::Folded))
+        })
+        .cloned()
+        .collect();
+    for target in in_fold {
+        found.remove(&target);
+    }
+
+    let stale: Vec<PathBuf> = store
+        .iter()
+        .filter(|(t, e)| e.belongs_to(t, &canonical_package, &absolute_target))
+        .map(|(t, _)| t.to_owned())
+        .collect();
+
+    info!(
+        "Found {} paths for package {:?} (replacing {} store entries)",
+        found.len(),
+        package,
+        stale.len()
+    );
+
+    for (target, entry) in found.iter() {
+        debug!("Found {} {:?} -> {:?}", entry.kind, target, entry.source);
+    }
+
+    if options.dry_run {
+        return Ok(());
+    }
+
+    for target in stale {
+        store.remove(&target);
+    }
+    for (target, entry) in found {
+        store.insert(target, entry);
+    }
+
+    Ok(())
+}
+
+/// Get the file a symlink points to.
+///
+/// The canonical path is used if the destination exists so links through other links are
+/// resolved to the final file. Otherwise the absolute path of the (broken) destination is used.
+fn resolve_link(link: &Path) -> Result<PathBuf> {
+    if let Ok(p) = link.canonicalize() {
+        return Ok(p);
+    }
+
+    let dest = link.read_link()?;
+    let dest = match link.parent() {
+        Some(parent) if dest.is_relative() => parent.join(dest),
+        _ => dest,
+    };
+    Ok(dest.absolutize()?.into_owned())
+}
+
+/// Patterns for files that belong to the package's repository or editor rather than the
+/// package. They can be re-included with a negated pattern.
+const DEFAULT_IGNORES: &[&str] = &[
+    ".git",
+    ".gitignore",
+    ".gitmodules",
+    ".hg",
+    ".svn",
+    "CVS",
+    "/README*",
+    "/LICENSE*",
+    "/COPYING",
+    "/lash.toml",
+    "*~",
+    "*.swp",
+    ".#*",
+    "#*#",
+    ".DS_Store",
+];
+
+/// Name of the file inside a package listing the paths that shouldn't be linked
+pub const IGNORE_FILE: &str = ".lashignore";
+
+/// Build the matcher for the paths inside `package` that shouldn't be linked.
+///
+/// Uses gitignore syntax. The default patterns come first, then the package's [IGNORE_FILE] and
+/// then the `patterns` from the configuration and command line so later patterns can override
+/// earlier ones.
+fn package_ignores(package: &Path, patterns: &[String]) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(package);
+
+    for pattern in DEFAULT_IGNORES.iter().copied().chain(once(IGNORE_FILE)) {
+        builder.add_line(None, pattern)?;
+    }
+
+    let ignore_file = package.join(IGNORE_FILE);
+    if ignore_file.is_file()
+        && let Some(e) = builder.add(&ignore_file)
+    {
+        error!("Could not read {:?}", ignore_file);
+        return Err(e.into());
+    }
+
+    for pattern in patterns {
+        builder.add_line(None, pattern)?;
+    }
+
+    Ok(builder.build()?)
+}
+
+/// The install modes for the paths inside a package, from [Options::mode] and the patterns in
+/// [Options::modes].
+pub(crate) struct InstallModes {
+    default: InstallMode,
+    /// Each pattern's length, matcher and mode, longest pattern first
+    patterns: Vec<(usize, Gitignore, InstallMode)>,
+}
+
+impl InstallModes {
+    /// Build the matchers for the patterns (gitignore syntax) relative to `package`.
+    pub(crate) fn new(options: &Options, package: &Path) -> Result<Self> {
+        let mut patterns = Vec::new();
+        for (pattern, mode) in options.modes.iter() {
+            let mut builder = GitignoreBuilder::new(package);
+            builder.add_line(None, pattern)?;
+            patterns.push((pattern.len(), builder.build()?, *mode));
+        }
+        patterns.sort_by_key(|(len, _, _)| std::cmp::Reverse(*len));
+
+        Ok(Self {
+            default: options.mode,
+            patterns,
+        })
+    }
+
+    /// Get the mode for `path`, relative to the package. Patterns matching a directory apply to
+    /// everything inside it and the longest matching pattern wins.
+    pub(crate) fn get(&self, path: &Path, is_dir: bool) -> InstallMode {
+        self.patterns
+            .iter()
+            .find(|(_, matcher, _)| {
+                matcher
+                    .matched_path_or_any_parents(path, is_dir)
+                    .is_ignore()
+            })
+            .map_or(self.default, |(_, _, mode)| *mode)
+    }
 }
 
 /// Get all of the [`Link`]s for a package. A [`Link`] is generated for each file or directory
@@ -222,14 +1040,40 @@ where
 /// `uninstall` causes the [`Link`]s to be generated in the reverse order (files then directories)
 /// instead of directories then files.
 ///
-/// `map_dots` calls [map_path_dots] on each of the target files/directories.
-fn get_paths(package: &Path, target: &Path, map_dots: bool, uninstall: bool) -> Result<Vec<Link>> {
+/// The path of each file/directory relative to the package is renamed by the [NameMapping] built
+/// from `options.dotfiles` and `options.rename`.
+///
+/// Paths matching the default ignore patterns, the package's [IGNORE_FILE] or `options.ignore`
+/// are skipped along with everything inside them.
+///
+/// Subdirectories of the package in `options.targets` are linked to their own target directory
+/// instead, with everything inside them placed relative to that directory.
+///
+/// Each [`Link`] is given its install mode from [InstallModes].
+pub(crate) fn get_paths(
+    options: &Options,
+    package: &Path,
+    target: &Path,
+    uninstall: bool,
+) -> Result<Vec<Link>> {
     let mut links = Vec::new();
+    let ignores = package_ignores(package, &options.ignore)?;
+    let modes = InstallModes::new(options, package)?;
+    let mapping = NameMapping::new(options)?;
 
     for res in WalkDir::new(package)
         .min_depth(1)
         .contents_first(uninstall)
         .into_iter()
+        .filter_entry(|e| {
+            let ignored = ignores
+                .matched(e.path(), e.file_type().is_dir())
+                .is_ignore();
+            if ignored {
+                debug!("Ignoring {:?}", e.path());
+            }
+            !ignored
+        })
     {
         match res {
             Err(e) => return Err(e.into()),
@@ -239,9 +1083,21 @@ fn get_paths(package: &Path, target: &Path, map_dots: bool, uninstall: bool) ->
                 // Remove the current dir from the path
                 let path = comp.strip_prefix(package)?;
 
-                // Get absolute path to link origin
-                let raw_target = target.join(path);
-                let raw_target = match raw_target.absolutize() {
+                // Get absolute path to link origin, the most specific subtree target wins. Only
+                // the part of the path inside the package (or subtree) is renamed
+                let raw_target = match options
+                    .targets
+                    .iter()
+                    .filter(|(subtree, _)| path.starts_with(subtree))
+                    .max_by_key(|(subtree, _)| subtree.components().count())
+                {
+                    Some((subtree, subtree_target)) => match path.strip_prefix(subtree)? {
+                        relative if relative.as_os_str().is_empty() => subtree_target.to_owned(),
+                        relative => subtree_target.join(mapping.map(relative)?),
+                    },
+                    None => target.join(mapping.map(path)?),
+                };
+                let mapped_target = match raw_target.absolutize() {
                     Err(e) => {
                         error!(
                             "Could not get absolute path for {:?}. Does the current directory exist?",
@@ -251,10 +1107,6 @@ fn get_paths(package: &Path, target: &Path, map_dots: bool, uninstall: bool) ->
                     }
                     Ok(p) => p.into_owned(),
                 };
-                let mapped_target = match map_dots {
-                    true => map_path_dots(raw_target)?,
-                    false => raw_target,
-                };
 
                 // Get canonical path to file inside package
                 let source = match entry.path().canonicalize() {
@@ -271,6 +1123,7 @@ fn get_paths(package: &Path, target: &Path, map_dots: bool, uninstall: bool) ->
                 links.push(Link {
                     source,
                     target: mapped_target.to_path_buf(),
+                    mode: modes.get(path, entry.file_type().is_dir()),
                 });
             }
         }
@@ -278,132 +1131,3 @@ fn get_paths(package: &Path, target: &Path, map_dots: bool, uninstall: bool) ->
 
     Ok(links)
 }
-
-fn check_zombies(
-    package: &Path,
-    target: &Path,
-    options: &Options,
-    store: &mut HashMap<PathBuf, PathBuf>,
-) -> Result<()> {
-    let mut clean_dirh: HashSet<PathBuf> = HashSet::new();
-    let mut clean_dirq: VecDeque<PathBuf> = VecDeque::new();
-    // Only needed for dry-run mode
-    let mut cleaned_files: HashSet<PathBuf> = HashSet::new();
-
-    info!(
-        "Checking destination for dangling links to package {:?}",
-        package
-    );
-
-    let absolute_target = match target.absolutize() {
-        Err(e) => {
-            error!(
-                "Could not get absolute path of {:?}. Does the current directory exist?",
-                target
-            );
-            return Err(e.into());
-        }
-        Ok(p) => p,
-    };
-    let canonicalized_package = match package.canonicalize() {
-        Err(e) => {
-            error!("Could not get canonicalized path of {:?}.", package);
-            return Err(e.into());
-        }
-        Ok(p) => p,
-    };
-
-    let mut keys_to_remove: HashSet<PathBuf> = HashSet::new();
-    for (target, _source) in store
-        .iter()
-        .filter(|(t, s)| t.starts_with(&absolute_target) && s.starts_with(&canonicalized_package))
-    {
-        debug!(
-            "Store entry found for this package+target: {:?}",
-            store.get(target).unwrap()
-        );
-        match target.try_exists() {
-            Ok(false) => {
-                if !target.is_symlink() {
-                    // file doesn't exist, somehow store is out of sync
-                    info!(
-                        "Couldn't find link for {:?}, removing from store...",
-                        target
-                    );
-                    keys_to_remove.insert(target.to_path_buf());
-                    continue;
-                }
-
-                // broken symbolic link
-                let link_dest = match target.read_link() {
-                    Err(e) => {
-                        error!("Could not get link destination for {:?}", target);
-                        return Err(e.into());
-                    }
-                    Ok(p) => p,
-                };
-
-                if link_dest.starts_with(&canonicalized_package) && !link_dest.exists() {
-                    info!("Removing zombie link {:?}", target);
-                    if let Some(parent) = target.parent() {
-                        if !clean_dirh.contains(parent) {
-                            clean_dirq.push_back(parent.to_path_buf());
-                            clean_dirh.insert(parent.to_path_buf());
-                        }
-                    }
-                    if options.dry_run {
-                        cleaned_files.insert(target.to_path_buf());
-                    } else {
-                        let res = remove_file(target);
-                        keys_to_remove.insert(target.to_path_buf());
-                        debug!("remove_file result {:?}", res);
-                    }
-                }
-            }
-            Ok(true) => { // dir/target exists so nothing to do
-            }
-            Err(e) => {
-                error!("Could not check if {:?} exists.", target);
-                return Err(e.into());
-            }
-        }
-    }
-    for key in keys_to_remove {
-        store.remove(&key);
-    }
-
-    while !clean_dirq.is_empty() {
-        let entry = clean_dirq.pop_front().unwrap();
-
-        if store.contains_key(&entry) {
-            if !options.dry_run && entry.read_dir().unwrap().next().is_none() {
-                info!("Removing zombie dir {:?}", &entry);
-                let res = remove_dir(&entry);
-                debug!("remove_dir result {:?}", res);
-                store.remove(&entry);
-                if let Some(parent) = entry.parent() {
-                    if !clean_dirh.contains(parent) {
-                        clean_dirq.push_back(parent.to_path_buf());
-                        clean_dirh.insert(parent.to_path_buf());
-                    }
-                }
-            } else if options.dry_run
-                && entry
-                    .read_dir()
-                    .unwrap()
-                    .all(|e| cleaned_files.contains(&e.unwrap().path()))
-            {
-                info!("Removing zombie dir {:?}", &entry);
-                cleaned_files.insert(entry.to_path_buf());
-                if let Some(parent) = entry.parent() {
-                    if !clean_dirh.contains(parent) {
-                        clean_dirq.push_back(parent.to_path_buf());
-                        clean_dirh.insert(parent.to_path_buf());
-                    }
-                }
-            }
-        }
-    }
-
-    Ok(())
-}
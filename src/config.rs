@@ -1,18 +1,76 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 use config::{ConfigError, File};
 use dirs::config_dir;
 use serde_derive::Deserialize;
 
+use crate::cli::{AdoptStrategy, ConflictPolicy, InstallMode, LinkStyle};
+use crate::mapping::RenameRule;
+
 /// This struct is what defines which options are supported in the TOML configuration files.
 ///
 /// All of the options are optional.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub verbose: Option<bool>,
     pub dotfiles: Option<bool>,
+    /// Rules renaming the paths in packages, applied in order after the `dotfiles` rule
+    pub rename: Option<Vec<RenameRule>>,
+    /// Link whole directories instead of each file inside them where possible
+    pub fold: Option<bool>,
     pub target: Option<PathBuf>,
-    pub adopt: Option<bool>,
+    /// Whether links are created with absolute or relative destinations
+    pub link_style: Option<LinkStyle>,
+    /// Whether files are installed as symlinks, hard links or copies
+    pub mode: Option<InstallMode>,
+    /// Either `true` to adopt files that are in the way or the strategy to adopt them with
+    pub adopt: Option<AdoptConfig>,
+    /// Patterns (gitignore syntax) for paths in packages that shouldn't be linked
+    pub ignore: Option<Vec<String>>,
+    /// What to do when something is in the way of a link
+    pub on_conflict: Option<ConflictPolicy>,
+    /// Directory to keep the store in
+    pub store: Option<PathBuf>,
+    /// Named store to use inside the store directory
+    pub profile: Option<String>,
+    /// Commands to run before and after packages are linked or unlinked
+    #[serde(default)]
+    pub hooks: Hooks,
+}
+
+/// Shell commands to run when a package changes. Each one is run with `sh -c` from the package
+/// directory, see [crate::hooks::run] for the environment they are given.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Hooks {
+    /// Run before a package is linked (or relinked), the package is skipped if it fails
+    pub pre_link: Option<String>,
+    /// Run after a package is linked (or relinked), the package is reported as failed if it fails
+    pub post_link: Option<String>,
+    /// Run before a package is unlinked, the package is skipped if it fails
+    pub pre_unlink: Option<String>,
+    /// Run after a package is unlinked, the package is reported as failed if it fails
+    pub post_unlink: Option<String>,
+}
+
+impl Hooks {
+    /// Merge `other` over these hooks, replacing the hooks it sets.
+    fn with(&self, other: &Hooks) -> Self {
+        Self {
+            pre_link: other.pre_link.to_owned().or(self.pre_link.to_owned()),
+            post_link: other.post_link.to_owned().or(self.post_link.to_owned()),
+            pre_unlink: other.pre_unlink.to_owned().or(self.pre_unlink.to_owned()),
+            post_unlink: other.post_unlink.to_owned().or(self.post_unlink.to_owned()),
+        }
+    }
+}
+
+/// The value of the adopt option in the configuration files.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(untagged)]
+pub enum AdoptConfig {
+    Enabled(bool),
+    Strategy(AdoptStrategy),
 }
 
 impl Config {
@@ -33,4 +91,95 @@ impl Config {
 
         config.try_deserialize()
     }
+
+    /// Merge the configuration of a package over this configuration.
+    ///
+    /// The adopt and on_conflict options are taken together so a package setting either one
+    /// replaces the conflict handling of the other configuration files completely.
+    pub fn with_package(&self, package: &PackageConfig) -> Self {
+        let (adopt, on_conflict) = match package.adopt.is_some() || package.on_conflict.is_some() {
+            true => (package.adopt, package.on_conflict),
+            false => (self.adopt, self.on_conflict),
+        };
+
+        Self {
+            dotfiles: package.dotfiles.or(self.dotfiles),
+            rename: match (&self.rename, &package.rename) {
+                (Some(rename), Some(package_rename)) => {
+                    Some(rename.iter().chain(package_rename).cloned().collect())
+                }
+                (rename, package_rename) => package_rename.to_owned().or(rename.to_owned()),
+            },
+            fold: package.fold.or(self.fold),
+            target: package.target.to_owned().or(self.target.to_owned()),
+            link_style: package.link_style.or(self.link_style),
+            mode: package.mode.or(self.mode),
+            adopt,
+            ignore: match (&self.ignore, &package.ignore) {
+                (Some(ignore), Some(package_ignore)) => {
+                    Some(ignore.iter().chain(package_ignore).cloned().collect())
+                }
+                (ignore, package_ignore) => package_ignore.to_owned().or(ignore.to_owned()),
+            },
+            on_conflict,
+            hooks: self.hooks.with(&package.hooks),
+            ..self.to_owned()
+        }
+    }
+}
+
+/// Name of the configuration file at the root of a package
+pub const PACKAGE_CONFIG: &str = "lash.toml";
+
+/// The options a package can set for itself in a [PACKAGE_CONFIG] file at the root of the
+/// package. These are merged over the workarea and global configuration files.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PackageConfig {
+    /// Short description of the package
+    pub description: Option<String>,
+    pub dotfiles: Option<bool>,
+    /// Rules renaming the paths in the package, applied after the rules from the other
+    /// configuration files
+    pub rename: Option<Vec<RenameRule>>,
+    /// Link whole directories instead of each file inside them where possible
+    pub fold: Option<bool>,
+    /// Target directory for the package. Relative paths are relative to the package
+    pub target: Option<PathBuf>,
+    /// Whether links are created with absolute or relative destinations
+    pub link_style: Option<LinkStyle>,
+    /// Whether the package's files are installed as symlinks, hard links or copies
+    pub mode: Option<InstallMode>,
+    /// Install modes for the paths in the package matching each pattern (gitignore syntax),
+    /// overriding `mode`. The longest matching pattern wins
+    pub modes: Option<BTreeMap<String, InstallMode>>,
+    /// Either `true` to adopt files that are in the way or the strategy to adopt them with
+    pub adopt: Option<AdoptConfig>,
+    /// Patterns (gitignore syntax) for paths in the package that shouldn't be linked, added to
+    /// the patterns from the other configuration files
+    pub ignore: Option<Vec<String>>,
+    /// What to do when something is in the way of a link
+    pub on_conflict: Option<ConflictPolicy>,
+    /// Target directories for subdirectories of the package, used instead of the package's
+    /// target for everything inside them. Relative paths are relative to the package
+    pub targets: Option<BTreeMap<PathBuf, PathBuf>>,
+    /// Commands to run before and after the package is linked or unlinked, replacing the hooks
+    /// of the same name from the other configuration files
+    #[serde(default)]
+    pub hooks: Hooks,
+}
+
+impl PackageConfig {
+    /// Attempts to read the configuration file at the root of `package`. A package without one
+    /// has an empty configuration.
+    pub fn new(package: &Path) -> Result<Self, ConfigError> {
+        let path = package.join(PACKAGE_CONFIG);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+
+        config::Config::builder()
+            .add_source(File::from(path))
+            .build()?
+            .try_deserialize()
+    }
 }
@@ -0,0 +1,225 @@
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use anyhow::{Result, anyhow};
+use similar::TextDiff;
+
+use crate::adopt;
+use crate::link::Link;
+use crate::options::Options;
+use crate::plan::Action;
+
+/// Asks the user before performing actions that throw away the contents of a file.
+///
+/// Only asks when stdin is a terminal and `--yes` wasn't given, otherwise every action is
+/// performed without asking so lash can still be used from scripts.
+#[derive(Debug)]
+pub struct Confirm {
+    /// Whether to ask at all
+    ask: bool,
+    /// The user answered "all", don't ask again
+    all: bool,
+    /// The user answered "quit", refuse everything from now on
+    quit: bool,
+}
+
+impl Confirm {
+    pub fn new(options: &Options) -> Self {
+        Self {
+            ask: Self::can_ask(options),
+            all: false,
+            quit: false,
+        }
+    }
+
+    /// Check if the user will be asked before performing actions with these options.
+    pub fn can_ask(options: &Options) -> bool {
+        !options.yes && !options.dry_run && io::stdin().is_terminal()
+    }
+
+    /// Check if `action` should be performed. Returns an error if the user has chosen to quit.
+    pub fn confirm(&mut self, action: &Action) -> Result<bool> {
+        if self.quit {
+            return Err(anyhow!("Cancelled"));
+        }
+        if !self.ask || self.all || !action.is_destructive() {
+            return Ok(true);
+        }
+
+        let diff = action_diff(action);
+        println!("{}", action);
+        if let Some(diff) = &diff {
+            print!("{}", diff);
+        }
+
+        let stdin = io::stdin();
+        loop {
+            print!("Continue? [y]es, [n]o, [a]ll, [q]uit, [d]iff: ");
+            io::stdout().flush()?;
+
+            let mut answer = String::new();
+            if stdin.lock().read_line(&mut answer)? == 0 {
+                // Nobody left to answer
+                self.quit = true;
+                return Err(anyhow!("Cancelled"));
+            }
+
+            match answer.trim().to_lowercase().as_str() {
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                "a" | "all" => {
+                    self.all = true;
+                    return Ok(true);
+                }
+                "q" | "quit" => {
+                    self.quit = true;
+                    return Err(anyhow!("Cancelled"));
+                }
+                "d" | "diff" => match &diff {
+                    Some(diff) => print!("{}", diff),
+                    None => println!("No differences to show"),
+                },
+                _ => println!("Please answer y, n, a, q or d"),
+            }
+        }
+    }
+
+    /// Ask whether to move `backup` back to `target` now that the link that displaced it has been
+    /// removed.
+    pub fn restore(&mut self, target: &Path, backup: &Path) -> Result<bool> {
+        if self.quit {
+            return Err(anyhow!("Cancelled"));
+        }
+        if !self.ask || self.all {
+            return Ok(self.all);
+        }
+
+        println!("{:?} was backed up to {:?}", target, backup);
+
+        let stdin = io::stdin();
+        loop {
+            print!("Restore it? [y]es, [n]o, [a]ll, [q]uit: ");
+            io::stdout().flush()?;
+
+            let mut answer = String::new();
+            if stdin.lock().read_line(&mut answer)? == 0 {
+                self.quit = true;
+                return Err(anyhow!("Cancelled"));
+            }
+
+            match answer.trim().to_lowercase().as_str() {
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                "a" | "all" => {
+                    self.all = true;
+                    return Ok(true);
+                }
+                "q" | "quit" => {
+                    self.quit = true;
+                    return Err(anyhow!("Cancelled"));
+                }
+                _ => println!("Please answer y, n, a or q"),
+            }
+        }
+    }
+
+    /// Ask whether to adopt the target of `link` or keep the package file, moving the target to
+    /// `backup`. Keeps the newer file without asking if lash can't ask. Returns `None` if the
+    /// file should be left alone.
+    pub fn choose(&mut self, link: &Link, backup: &Path) -> Result<Option<Action>> {
+        if self.quit {
+            return Err(anyhow!("Cancelled"));
+        }
+        if !self.ask {
+            return Action::keep_newer(link, backup).map(Some);
+        }
+
+        let diff = file_diff(&link.source, &link.target);
+        println!(
+            "{:?} and {:?} are different, which version should be kept?",
+            link.source, link.target
+        );
+        if let Some(diff) = &diff {
+            print!("{}", diff);
+        }
+
+        let stdin = io::stdin();
+        loop {
+            print!("Keep the [t]arget, [p]ackage, [s]kip, [q]uit, [d]iff: ");
+            io::stdout().flush()?;
+
+            let mut answer = String::new();
+            if stdin.lock().read_line(&mut answer)? == 0 {
+                self.quit = true;
+                return Err(anyhow!("Cancelled"));
+            }
+
+            match answer.trim().to_lowercase().as_str() {
+                "t" | "target" => {
+                    adopt::check(link)?;
+                    return Ok(Some(Action::Adopt { link: link.clone() }));
+                }
+                "p" | "package" => {
+                    return Ok(Some(Action::Backup {
+                        link: link.clone(),
+                        backup: backup.to_owned(),
+                    }));
+                }
+                "s" | "skip" => return Ok(None),
+                "q" | "quit" => {
+                    self.quit = true;
+                    return Err(anyhow!("Cancelled"));
+                }
+                "d" | "diff" => match &diff {
+                    Some(diff) => print!("{}", diff),
+                    None => println!("No differences to show"),
+                },
+                _ => println!("Please answer t, p, s, q or d"),
+            }
+        }
+    }
+}
+
+/// Get a unified diff of the contents that will be replaced by `action` and what will replace
+/// them. Only text files are compared.
+fn action_diff(action: &Action) -> Option<String> {
+    match action {
+        // The package file is replaced by the target
+        Action::Adopt { link } => file_diff(&link.source, &link.target),
+        // The target is replaced by the package file
+        Action::Overwrite { target, source } => file_diff(target, source),
+        _ => None,
+    }
+}
+
+/// Get a unified diff from the contents of `old` to the contents of `new`.
+pub fn file_diff(old: &Path, new: &Path) -> Option<String> {
+    let (Ok(old_data), Ok(new_data)) = (fs::read(old), fs::read(new)) else {
+        return None;
+    };
+    let (Some(old_text), Some(new_text)) = (text(old_data), text(new_data)) else {
+        return Some("Binary files differ\n".to_string());
+    };
+
+    let diff = TextDiff::from_lines(&old_text, &new_text)
+        .unified_diff()
+        .header(&old.to_string_lossy(), &new.to_string_lossy())
+        .to_string();
+    match diff.is_empty() {
+        true => None,
+        false => Some(diff),
+    }
+}
+
+/// Convert the contents of a file to a string if they look like text.
+fn text(data: Vec<u8>) -> Option<String> {
+    let text = String::from_utf8(data).ok()?;
+    match text.contains('\0') {
+        true => None,
+        false => Some(text),
+    }
+}
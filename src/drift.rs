@@ -0,0 +1,107 @@
+use std::path::{Path, PathBuf};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use anyhow::{Result, anyhow};
+
+use crate::adopt::copy_metadata;
+use crate::confirm::file_diff;
+use crate::options::Options;
+use crate::store::{Entry, EntryKind, Store, content_hash, same_file};
+use crate::store_command::resolve_paths;
+
+/// Find the hard links and copies for `paths` (packages or target paths, see [resolve_paths]),
+/// or every hard link and copy in the store when no paths are given. Sorted by target path.
+fn copies(store: &Store, paths: &[PathBuf]) -> Result<Vec<(PathBuf, Entry)>> {
+    let targets: Vec<PathBuf> = match paths.is_empty() {
+        true => store.iter().map(|(t, _)| t.to_owned()).collect(),
+        false => resolve_paths(store, paths)?.into_iter().collect(),
+    };
+
+    let mut copies: Vec<(PathBuf, Entry)> = targets
+        .into_iter()
+        .filter_map(|t| store.get(&t).cloned().map(|e| (t, e)))
+        .filter(|(_, e)| matches!(e.kind, EntryKind::Hardlink | EntryKind::Copy))
+        .collect();
+    copies.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(copies)
+}
+
+/// Check if the installed file at `target` has drifted from the package file it was installed
+/// from. Hard links that still share the package file never drift.
+fn drifted(target: &Path, entry: &Entry) -> bool {
+    target.is_file()
+        && entry.source.is_file()
+        && !(entry.kind == EntryKind::Hardlink && same_file(target, &entry.source))
+}
+
+/// Print a unified diff from each package file to its installed hard link or copy.
+///
+/// Only files whose contents differ are shown. Binary files are only reported as differing.
+pub fn diff(store: &Store, paths: &[PathBuf]) -> Result<()> {
+    for (target, entry) in copies(store, paths)? {
+        if !drifted(&target, &entry) {
+            continue;
+        }
+        if let Some(diff) = file_diff(&entry.source, &target) {
+            print!("{}", diff);
+        }
+    }
+    Ok(())
+}
+
+/// Copy the changes made to installed hard links and copies back into the package.
+///
+/// The contents recorded in the store when the file was installed tell which side changed. Files
+/// where only the package changed are left for `lash relink` to update. When both sides changed
+/// since then the file is only pulled with `force`, otherwise it is reported and the command
+/// fails after pulling the other files.
+pub fn pull(options: &Options, store: &mut Store, paths: &[PathBuf], force: bool) -> Result<()> {
+    let mut conflicts = 0;
+
+    for (target, entry) in copies(store, paths)? {
+        if !drifted(&target, &entry) {
+            continue;
+        }
+
+        let hash = content_hash(&target)?;
+        if entry.hash == Some(hash) {
+            debug!("{:?} hasn't changed since it was installed", target);
+            continue;
+        }
+        if entry.hash != Some(content_hash(&entry.source)?) && !force {
+            error!(
+                "Both {:?} and {:?} have changed since it was installed, use --force to replace \
+                 the package file",
+                target, entry.source
+            );
+            conflicts += 1;
+            continue;
+        }
+
+        info!("Pulling {:?} into {:?}", target, entry.source);
+        if options.dry_run {
+            continue;
+        }
+        // Hard links that were broken by the change are remade by the next relink
+        store.journal.copy(&target, &entry.source)?;
+        copy_metadata(&target, &entry.source)?;
+        store.insert(
+            target,
+            Entry {
+                hash: Some(hash),
+                ..entry
+            },
+        );
+    }
+
+    store.flush()?;
+    match conflicts {
+        0 => Ok(()),
+        n => Err(anyhow!(
+            "{} files changed in both places were not pulled",
+            n
+        )),
+    }
+}
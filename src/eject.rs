@@ -0,0 +1,79 @@
+use std::path::{Path, PathBuf};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use anyhow::Result;
+use walkdir::WalkDir;
+
+use crate::adopt::{copy_dir_metadata, copy_metadata};
+use crate::options::Options;
+use crate::store::{EntryKind, Store, same_file};
+use crate::store_command::resolve_paths;
+
+/// Copy the package directory `from` to `to` keeping the metadata of everything inside it.
+fn copy_dir(store: &mut Store, from: &Path, to: &Path) -> Result<()> {
+    for dir_entry in WalkDir::new(from) {
+        let dir_entry = dir_entry?;
+        let path = to.join(dir_entry.path().strip_prefix(from)?);
+        match dir_entry.file_type().is_dir() {
+            true => store.journal.create_dir_all(&path)?,
+            false => {
+                store.journal.copy(dir_entry.path(), &path)?;
+                copy_metadata(dir_entry.path(), &path)?;
+            }
+        }
+    }
+
+    copy_dir_metadata(from, to)
+}
+
+/// Stop managing packages or target paths without removing the files from the target directory.
+///
+/// Each link created by lash is replaced by a copy of the package file, keeping its metadata, and
+/// the entries are removed from the store. Folded directories are replaced by a copy of the
+/// package directory and hard links by a copy so they no longer share the package file. Copies
+/// and other directories are left as they are and the package isn't changed.
+pub fn eject(options: &Options, store: &mut Store, paths: &[PathBuf]) -> Result<()> {
+    let mut targets: Vec<PathBuf> = resolve_paths(store, paths)?.into_iter().collect();
+    targets.sort();
+
+    for target in targets {
+        let Some(entry) = store.get(&target).cloned() else {
+            continue;
+        };
+
+        let is_link =
+            target.is_symlink() && target.canonicalize().ok().as_ref() == Some(&entry.source);
+
+        // Hard links share the package file so they are replaced too
+        let is_hardlink = entry.kind == EntryKind::Hardlink && same_file(&target, &entry.source);
+
+        if (entry.kind == EntryKind::Link && is_link && entry.source.is_file()) || is_hardlink {
+            info!("Replacing {:?} with a copy of {:?}", target, entry.source);
+            if !options.dry_run {
+                store.journal.remove_file(&target)?;
+                store.journal.copy(&entry.source, &target)?;
+                copy_metadata(&entry.source, &target)?;
+            }
+        } else if entry.kind == EntryKind::Folded && is_link && entry.source.is_dir() {
+            info!("Replacing {:?} with a copy of {:?}", target, entry.source);
+            if !options.dry_run {
+                store.journal.remove_file(&target)?;
+                copy_dir(store, &entry.source, &target)?;
+            }
+        } else if entry.kind == EntryKind::Link || entry.kind == EntryKind::Folded {
+            warn!(
+                "{:?} is no longer a link to {:?}, leaving it as it is",
+                target, entry.source
+            );
+        }
+
+        debug!("Forgetting {:?}", target);
+        if !options.dry_run {
+            store.remove(&target);
+        }
+    }
+
+    store.flush()
+}
@@ -0,0 +1,204 @@
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use anyhow::{Result, anyhow};
+
+use crate::journal::{Change, file_hash};
+use crate::options::Options;
+use crate::store::Store;
+
+/// Print the operations recorded in the journal, newest first.
+pub fn history(store: &Store, limit: Option<usize>) -> Result<()> {
+    let operations = store.journal.operations()?;
+
+    for op in operations.iter().rev().take(limit.unwrap_or(usize::MAX)) {
+        let time =
+            humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(op.timestamp));
+
+        let mut notes = vec![format!("{} changes", op.changes.len())];
+        if let Some(id) = op.undo_of {
+            notes.push(format!("undoes {}", id));
+        }
+        if let Some(id) = op.undone_by {
+            notes.push(format!("undone by {}", id));
+        }
+
+        println!(
+            "{}\t{}\t{}\t({})",
+            op.id,
+            time,
+            op.command,
+            notes.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Reverse the changes made by operation `id`, or by the most recent operation that hasn't been
+/// undone and isn't itself an undo.
+///
+/// The changes are reversed newest first. A change is skipped with a warning if the file system
+/// no longer matches what the operation left behind so nothing made since is overwritten. The undo
+/// is recorded in the journal like any other operation so it can be undone in turn.
+pub fn undo(options: &Options, store: &mut Store, id: Option<u64>) -> Result<()> {
+    let operations = store.journal.operations()?;
+
+    let operation = match id {
+        Some(id) => operations
+            .iter()
+            .find(|o| o.id == id)
+            .ok_or(anyhow!("No operation with ID {} in the journal", id))?,
+        None => operations
+            .iter()
+            .rev()
+            .find(|o| o.undone_by.is_none() && o.undo_of.is_none())
+            .ok_or(anyhow!("No operations left to undo"))?,
+    };
+
+    if let Some(by) = operation.undone_by {
+        return Err(anyhow!(
+            "Operation {} has already been undone by operation {}",
+            operation.id,
+            by
+        ));
+    }
+
+    info!("Undoing operation {}: {}", operation.id, operation.command);
+    store.journal.set_undo_of(operation.id);
+
+    for change in operation.changes.iter().rev() {
+        revert(options, store, change)?;
+    }
+
+    if !options.dry_run {
+        store.journal.mark_undone(operation.id)?;
+    }
+
+    Ok(())
+}
+
+/// Perform the inverse of a single change.
+fn revert(options: &Options, store: &mut Store, change: &Change) -> Result<()> {
+    match change {
+        Change::CreateDir { path } => {
+            if !path.is_dir() || path.is_symlink() {
+                warn!("Directory {:?} no longer exists, skipping", path);
+            } else if path.read_dir()?.next().is_some() {
+                warn!("Directory {:?} is not empty, not removing", path);
+            } else {
+                info!("Removing directory {:?}", path);
+                if !options.dry_run {
+                    store.journal.remove_dir(path)?;
+                }
+            }
+        }
+        Change::RemoveDir { path } => {
+            if path.is_dir() {
+                debug!("Directory {:?} already exists", path);
+            } else {
+                info!("Creating directory {:?}", path);
+                if !options.dry_run {
+                    store.journal.create_dir_all(path)?;
+                }
+            }
+        }
+        Change::CreateLink { link, dest } => {
+            if !link.is_symlink() || link.read_link()? != *dest {
+                warn!("{:?} is no longer a link to {:?}, skipping", link, dest);
+            } else {
+                info!("Removing link {:?} -> {:?}", link, dest);
+                if !options.dry_run {
+                    store.journal.remove_file(link)?;
+                }
+            }
+        }
+        Change::RemoveLink { link, dest } => {
+            if link.exists() || link.is_symlink() {
+                warn!(
+                    "{:?} already exists, not restoring link to {:?}",
+                    link, dest
+                );
+            } else {
+                info!("Restoring link {:?} -> {:?}", link, dest);
+                if !options.dry_run {
+                    store.journal.symlink(dest, link)?;
+                }
+            }
+        }
+        Change::RemoveFile { path, backup } => {
+            if path.exists() || path.is_symlink() {
+                warn!("{:?} already exists, not restoring it", path);
+            } else {
+                info!("Restoring file {:?}", path);
+                if !options.dry_run {
+                    store.journal.copy(backup, path)?;
+                }
+            }
+        }
+        Change::Rename {
+            from,
+            to,
+            hash,
+            dest,
+        } => {
+            if from.exists() || from.is_symlink() {
+                warn!("{:?} already exists, not moving {:?} back", from, to);
+            } else if !to.exists() && !to.is_symlink() {
+                warn!("{:?} no longer exists, skipping", to);
+            } else if !unchanged(to, *hash, dest.as_deref()) {
+                warn!("{:?} has changed since it was moved, skipping", to);
+            } else {
+                info!("Moving {:?} back to {:?}", to, from);
+                if !options.dry_run {
+                    store.journal.rename(to, from)?;
+                }
+            }
+        }
+        Change::WriteFile { path, hash, .. } if !unchanged(path, *hash, None) => {
+            warn!("{:?} has changed since it was written, skipping", path);
+        }
+        Change::WriteFile { path, backup, .. } => match backup {
+            Some(backup) => {
+                info!("Restoring previous contents of {:?}", path);
+                if !options.dry_run {
+                    store.journal.copy(backup, path)?;
+                }
+            }
+            None => {
+                info!("Removing file {:?}", path);
+                if !options.dry_run && fs::symlink_metadata(path).is_ok() {
+                    store.journal.remove_file(path)?;
+                }
+            }
+        },
+        Change::SetEntry {
+            target, previous, ..
+        } => {
+            debug!("Restoring store entry for {:?}", target);
+            if !options.dry_run {
+                match previous {
+                    Some(entry) => {
+                        store.insert(target.to_owned(), entry.as_ref().to_owned());
+                    }
+                    None => {
+                        store.remove(target);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that `path` still holds what a change left there, going by the [file_hash] of a file or
+/// the destination of a symlink. Changes recorded without either can't be checked.
+fn unchanged(path: &Path, hash: Option<u64>, dest: Option<&Path>) -> bool {
+    hash.is_none_or(|hash| file_hash(path) == Some(hash))
+        && dest.is_none_or(|dest| path.read_link().is_ok_and(|d| d == dest))
+}
@@ -0,0 +1,95 @@
+use std::fmt;
+use std::process::Command;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use anyhow::{Result, anyhow};
+
+use crate::config::Hooks;
+use crate::options::Options;
+use crate::plan::Plan;
+
+/// The points at which a package's hooks are run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    PreLink,
+    PostLink,
+    PreUnlink,
+    PostUnlink,
+}
+
+impl Hook {
+    /// Get the command configured for this hook, if any.
+    fn command<'a>(&self, hooks: &'a Hooks) -> Option<&'a str> {
+        match self {
+            Hook::PreLink => hooks.pre_link.as_deref(),
+            Hook::PostLink => hooks.post_link.as_deref(),
+            Hook::PreUnlink => hooks.pre_unlink.as_deref(),
+            Hook::PostUnlink => hooks.post_unlink.as_deref(),
+        }
+    }
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Hook::PreLink => write!(f, "pre-link"),
+            Hook::PostLink => write!(f, "post-link"),
+            Hook::PreUnlink => write!(f, "pre-unlink"),
+            Hook::PostUnlink => write!(f, "post-unlink"),
+        }
+    }
+}
+
+/// Run the command configured for `hook` for the package in `plan`.
+///
+/// The command is run with `sh -c` from the package directory with these environment variables:
+///
+/// - `LASH_HOOK`: the name of the hook, e.g. `pre-link`
+/// - `LASH_PACKAGE`: the canonical path to the package
+/// - `LASH_TARGET`: the absolute path to the target directory
+/// - `LASH_PATHS`: the paths in the target directory that are (or were) created, replaced or
+///   removed, one per line
+///
+/// Nothing is run when the plan doesn't change anything. In a dry run the command is printed
+/// instead. Fails if the command can't be run or exits unsuccessfully.
+pub fn run(options: &Options, hook: Hook, plan: &Plan) -> Result<()> {
+    let Some(command) = hook.command(&options.hooks) else {
+        return Ok(());
+    };
+
+    let paths: Vec<String> = plan
+        .actions
+        .iter()
+        .filter_map(|a| a.changed_path())
+        .map(|p| p.display().to_string())
+        .collect();
+    if paths.is_empty() {
+        debug!(
+            "Nothing changed in {:?}, not running {} hook",
+            plan.package, hook
+        );
+        return Ok(());
+    }
+
+    info!("Running {} hook: {}", hook, command);
+    if options.dry_run {
+        return Ok(());
+    }
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(&plan.package)
+        .env("LASH_HOOK", hook.to_string())
+        .env("LASH_PACKAGE", &plan.package)
+        .env("LASH_TARGET", &plan.target)
+        .env("LASH_PATHS", paths.join("\n"))
+        .status()?;
+
+    match status.success() {
+        true => Ok(()),
+        false => Err(anyhow!("The {} hook failed ({})", hook, status)),
+    }
+}
@@ -0,0 +1,452 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use anyhow::Result;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::store::{Entry, content_hash, now, os_path};
+
+/// Name of the journal file inside the journal directory
+const JOURNAL_FILE: &str = "journal.jsonl";
+/// Name of the file holding the ID of the last operation started, so the journal file doesn't
+/// have to be read to find it
+const LAST_ID_FILE: &str = "last_id";
+
+/// A single change made to the file system or the store by lash.
+///
+/// Every change records enough information for it to be reversed. Paths are written with
+/// [os_path] so names that aren't UTF-8 can be recorded too.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum Change {
+    /// A directory was created
+    CreateDir {
+        #[serde(with = "os_path")]
+        path: PathBuf,
+    },
+    /// An empty directory was removed
+    RemoveDir {
+        #[serde(with = "os_path")]
+        path: PathBuf,
+    },
+    /// A symlink was created at `link` pointing to `dest`
+    CreateLink {
+        #[serde(with = "os_path")]
+        link: PathBuf,
+        #[serde(with = "os_path")]
+        dest: PathBuf,
+    },
+    /// The symlink at `link` pointing to `dest` was removed
+    RemoveLink {
+        #[serde(with = "os_path")]
+        link: PathBuf,
+        #[serde(with = "os_path")]
+        dest: PathBuf,
+    },
+    /// The file at `path` was removed. Its contents were saved to `backup`.
+    RemoveFile {
+        #[serde(with = "os_path")]
+        path: PathBuf,
+        #[serde(with = "os_path")]
+        backup: PathBuf,
+    },
+    /// The file or directory at `from` was moved to `to`. The [content_hash] of a file or the
+    /// destination of a symlink is kept to check that `to` still holds what was moved.
+    Rename {
+        #[serde(with = "os_path")]
+        from: PathBuf,
+        #[serde(with = "os_path")]
+        to: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        hash: Option<u64>,
+        #[serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            with = "os_path::option"
+        )]
+        dest: Option<PathBuf>,
+    },
+    /// The file at `path` was written to, leaving contents with the [content_hash] `hash`. If a
+    /// file already existed its contents were saved to `backup`.
+    WriteFile {
+        #[serde(with = "os_path")]
+        path: PathBuf,
+        #[serde(with = "os_path::option")]
+        backup: Option<PathBuf>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        hash: Option<u64>,
+    },
+    /// The store entry for `target` was changed from `previous` to `current`
+    SetEntry {
+        #[serde(with = "os_path")]
+        target: PathBuf,
+        previous: Option<Box<Entry>>,
+        current: Option<Box<Entry>>,
+    },
+}
+
+/// A single line in the journal file.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+enum Record {
+    /// Start of a new operation
+    Begin {
+        id: u64,
+        timestamp: u64,
+        command: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        undo_of: Option<u64>,
+    },
+    /// A change made as part of operation `id`
+    Change { id: u64, change: Change },
+    /// Operation `id` was reversed by operation `by`
+    Undone { id: u64, by: u64 },
+}
+
+/// Everything that was recorded about one invocation of lash.
+#[derive(Debug)]
+pub struct Operation {
+    pub id: u64,
+    /// Seconds since the UNIX epoch when the operation started
+    pub timestamp: u64,
+    /// The command line lash was run with
+    pub command: String,
+    /// The changes made, in the order they were made
+    pub changes: Vec<Change>,
+    /// The operation this operation reversed if it was an undo
+    pub undo_of: Option<u64>,
+    /// The operation that reversed this operation
+    pub undone_by: Option<u64>,
+}
+
+/// The journal file of a store and the state of the operation currently being recorded.
+#[derive(Debug)]
+struct JournalFile {
+    /// Directory containing the journal file and the backups of changed files
+    dir: PathBuf,
+    /// The journal file opened for appending
+    file: File,
+    /// ID of the current operation
+    id: u64,
+    /// Whether the start of the current operation has been written yet
+    started: bool,
+    /// The operation the current operation is reversing
+    undo_of: Option<u64>,
+    /// Number of files backed up by the current operation
+    backups: usize,
+}
+
+/// An append-only log of every change lash makes.
+///
+/// The file system operations lash performs go through the journal so they are recorded as they
+/// happen. Files that are overwritten or removed are copied into the journal first so they can be
+/// restored by `lash undo`. A journal that isn't backed by a file performs the operations without
+/// recording anything.
+///
+/// An operation is only started in the journal when its first change is recorded, so runs that
+/// don't change anything don't show up in the history. Records are only synced to disk by
+/// [Journal::sync], which the store does whenever it is flushed.
+///
+/// The journal and its backups are never pruned, removing the journal directory forgets the
+/// history.
+#[derive(Debug, Default)]
+pub struct Journal {
+    inner: Option<JournalFile>,
+}
+
+impl Journal {
+    /// Open the journal in `dir` and prepare to record a new operation.
+    pub fn open(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        let path = dir.join(JOURNAL_FILE);
+        let last_id = fs::read_to_string(dir.join(LAST_ID_FILE))
+            .ok()
+            .and_then(|id| id.trim().parse().ok());
+        let last_id = match last_id {
+            Some(id) => id,
+            // Journals written before the last ID was kept have to be read
+            None if path.exists() => read_records(&path)?
+                .iter()
+                .filter_map(|r| match r {
+                    Record::Begin { id, .. } => Some(*id),
+                    _ => None,
+                })
+                .max()
+                .unwrap_or(0),
+            None => 0,
+        };
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        Ok(Self {
+            inner: Some(JournalFile {
+                dir: dir.to_owned(),
+                file,
+                id: last_id + 1,
+                started: false,
+                undo_of: None,
+                backups: 0,
+            }),
+        })
+    }
+
+    /// Get all of the operations recorded in the journal, oldest first.
+    pub fn operations(&self) -> Result<Vec<Operation>> {
+        let Some(inner) = &self.inner else {
+            return Ok(Vec::new());
+        };
+        let path = inner.dir.join(JOURNAL_FILE);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut operations: Vec<Operation> = Vec::new();
+        for record in read_records(&path)? {
+            match record {
+                Record::Begin {
+                    id,
+                    timestamp,
+                    command,
+                    undo_of,
+                } => operations.push(Operation {
+                    id,
+                    timestamp,
+                    command,
+                    changes: Vec::new(),
+                    undo_of,
+                    undone_by: None,
+                }),
+                Record::Change { id, change } => {
+                    if let Some(op) = operations.iter_mut().rev().find(|o| o.id == id) {
+                        op.changes.push(change);
+                    }
+                }
+                Record::Undone { id, by } => {
+                    if let Some(op) = operations.iter_mut().rev().find(|o| o.id == id) {
+                        op.undone_by = Some(by);
+                    }
+                }
+            }
+        }
+
+        Ok(operations)
+    }
+
+    /// Mark the current operation as reversing operation `id`.
+    pub fn set_undo_of(&mut self, id: u64) {
+        if let Some(inner) = &mut self.inner {
+            inner.undo_of = Some(id);
+        }
+    }
+
+    /// Record that operation `id` has been reversed by the current operation.
+    pub fn mark_undone(&mut self, id: u64) -> io::Result<()> {
+        let Some(inner) = &self.inner else {
+            return Ok(());
+        };
+        let by = inner.id;
+        self.write(&Record::Undone { id, by })
+    }
+
+    /// Record a change made as part of the current operation.
+    pub fn record(&mut self, change: Change) -> io::Result<()> {
+        let Some(inner) = &self.inner else {
+            return Ok(());
+        };
+        let id = inner.id;
+        self.write(&Record::Change { id, change })
+    }
+
+    /// Append a record to the journal, starting the current operation first if needed.
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        let Some(inner) = &mut self.inner else {
+            return Ok(());
+        };
+
+        if !inner.started {
+            let command = std::iter::once("lash".to_string())
+                .chain(std::env::args().skip(1))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let begin = Record::Begin {
+                id: inner.id,
+                timestamp: now(),
+                command,
+                undo_of: inner.undo_of,
+            };
+            fs::write(inner.dir.join(LAST_ID_FILE), format!("{}\n", inner.id))?;
+            writeln!(inner.file, "{}", serde_json::to_string(&begin)?)?;
+            inner.started = true;
+        }
+
+        writeln!(inner.file, "{}", serde_json::to_string(record)?)
+    }
+
+    /// Make sure the records written so far are on disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        match &self.inner {
+            Some(inner) if inner.started => inner.file.sync_data(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Copy the file at `path` into the journal so it can be restored later.
+    fn backup(&mut self, path: &Path) -> io::Result<Option<PathBuf>> {
+        let Some(inner) = &mut self.inner else {
+            return Ok(None);
+        };
+
+        let dir = inner.dir.join(inner.id.to_string());
+        fs::create_dir_all(&dir)?;
+        inner.backups += 1;
+        let backup = dir.join(inner.backups.to_string());
+
+        debug!("Backing up {:?} to {:?}", path, backup);
+        fs::copy(path, &backup)?;
+        Ok(Some(backup))
+    }
+
+    /// Create a directory and any missing parents.
+    pub fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        let missing: Vec<PathBuf> = path
+            .ancestors()
+            .take_while(|p| !p.as_os_str().is_empty() && !p.exists())
+            .map(|p| p.to_owned())
+            .collect();
+
+        for dir in missing.into_iter().rev() {
+            fs::create_dir(&dir)?;
+            self.record(Change::CreateDir { path: dir })?;
+        }
+        Ok(())
+    }
+
+    /// Remove an empty directory.
+    pub fn remove_dir(&mut self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)?;
+        self.record(Change::RemoveDir {
+            path: path.to_owned(),
+        })
+    }
+
+    /// Create a symlink at `link` pointing to `dest`.
+    pub fn symlink(&mut self, dest: &Path, link: &Path) -> io::Result<()> {
+        symlink(dest, link)?;
+        self.record(Change::CreateLink {
+            link: link.to_owned(),
+            dest: dest.to_owned(),
+        })
+    }
+
+    /// Create a hard link at `link` to the file at `source`.
+    pub fn hard_link(&mut self, source: &Path, link: &Path) -> io::Result<()> {
+        fs::hard_link(source, link)?;
+        self.record(Change::WriteFile {
+            path: link.to_owned(),
+            backup: None,
+            hash: file_hash(link),
+        })
+    }
+
+    /// Remove a file or symlink. Files are backed up first, for symlinks only the destination
+    /// needs recording.
+    pub fn remove_file(&mut self, path: &Path) -> io::Result<()> {
+        if path.is_symlink() {
+            let dest = path.read_link()?;
+            fs::remove_file(path)?;
+            return self.record(Change::RemoveLink {
+                link: path.to_owned(),
+                dest,
+            });
+        }
+
+        let backup = self.backup(path)?;
+        fs::remove_file(path)?;
+        match backup {
+            Some(backup) => self.record(Change::RemoveFile {
+                path: path.to_owned(),
+                backup,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Move a file or directory from `from` to `to`.
+    ///
+    /// Files and symlinks are copied and then removed if `to` is on a different file system,
+    /// directories can only be moved within a file system.
+    pub fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        match fs::rename(from, to) {
+            Err(e)
+                if e.kind() == io::ErrorKind::CrossesDevices
+                    && (from.is_symlink() || !from.is_dir()) =>
+            {
+                match from.is_symlink() {
+                    true => symlink(from.read_link()?, to)?,
+                    false => {
+                        fs::copy(from, to)?;
+                    }
+                }
+                fs::remove_file(from)?;
+            }
+            res => res?,
+        }
+        self.record(Change::Rename {
+            from: from.to_owned(),
+            to: to.to_owned(),
+            hash: file_hash(to),
+            dest: to.read_link().ok(),
+        })
+    }
+
+    /// Copy the contents of `from` to `to`. If `to` exists it is backed up first.
+    pub fn copy(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        let backup = match to.exists() {
+            true => self.backup(to)?,
+            false => None,
+        };
+        fs::copy(from, to)?;
+        self.record(Change::WriteFile {
+            path: to.to_owned(),
+            backup,
+            hash: file_hash(to),
+        })
+    }
+}
+
+/// The [content_hash] of the regular file at `path`, or `None` for anything else.
+pub fn file_hash(path: &Path) -> Option<u64> {
+    match path.is_file() && !path.is_symlink() {
+        true => content_hash(path).ok(),
+        false => None,
+    }
+}
+
+/// Read every record in the journal file at `path`.
+///
+/// A crash while writing could leave a partial record at the end of the file, these are ignored.
+fn read_records(path: &Path) -> Result<Vec<Record>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    for (n, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            Err(e) => warn!(
+                "Ignoring unreadable journal record on line {}: {}",
+                n + 1,
+                e
+            ),
+        }
+    }
+    Ok(records)
+}
@@ -1,5 +1,18 @@
+pub mod add;
+pub mod adopt;
 pub mod cli;
 pub mod command;
 pub mod config;
+pub mod confirm;
+pub mod drift;
+pub mod eject;
+pub mod history;
+pub mod hooks;
+pub mod journal;
 pub mod link;
+pub mod mapping;
 pub mod options;
+pub mod plan;
+pub mod status;
+pub mod store;
+pub mod store_command;
@@ -1,10 +1,69 @@
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Result;
+use path_absolutize::Absolutize;
+use serde_derive::Serialize;
+
+use crate::cli::{InstallMode, LinkStyle};
 
 /// Structure containing the source/target information for the link.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct Link {
     /// The location of the file that the link points to
     pub source: PathBuf,
     /// The name of the link on the file system
     pub target: PathBuf,
+    /// Whether a file is installed as a symlink, hard link or copy. Directories are only ever
+    /// folded into symlinks
+    pub mode: InstallMode,
+}
+
+impl Link {
+    /// Get the destination to write into the symlink at the target for `style`.
+    ///
+    /// Relative destinations are the shortest path from the directory containing the link to the
+    /// source. The directory is canonicalized (when it exists) so the destination still works if
+    /// it is reached through another symlink.
+    pub fn dest(&self, style: LinkStyle) -> Result<PathBuf> {
+        let source = self.source.absolutize()?;
+        let target = self.target.absolutize()?;
+        let parent = match target.parent() {
+            Some(parent) => parent.canonicalize().unwrap_or(parent.to_owned()),
+            None => return Ok(source.into_owned()),
+        };
+
+        match style {
+            LinkStyle::Absolute => Ok(source.into_owned()),
+            LinkStyle::Relative => Ok(relative_path(&parent, &source)),
+        }
+    }
+}
+
+/// Check if the destination of an existing symlink is written in `style`. Absolute destinations
+/// also need to be normalised so they are recreated if they contain `.` or `..`.
+pub fn has_style(dest: &Path, style: LinkStyle) -> Result<bool> {
+    // NOTE: Compare the str version because Paths will automatically resolve the relative paths
+    // to make them equivalent.
+    Ok(match style {
+        LinkStyle::Absolute => dest.as_os_str() == dest.absolutize()?.as_os_str(),
+        LinkStyle::Relative => dest.is_relative(),
+    })
+}
+
+/// Get the relative path from the directory `from` to `to`. Both paths must be absolute.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from
+        .iter()
+        .zip(to.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut path: PathBuf = from[common..]
+        .iter()
+        .map(|_| Component::ParentDir)
+        .collect();
+    path.extend(&to[common..]);
+    path
 }
@@ -22,25 +22,100 @@
 //! workarea.
 //!
 //! Configuration files should be called `lash.toml` and are (predictably) in the TOML format.
-//! Options are specified in the global namespace. No package specific options can be configured.
-//! To see the supported options in the configuration file see [Config](crate::config::Config)
+//! Options are specified in the global namespace. To see the supported options in the
+//! configuration file see [Config](crate::config::Config)
+//!
+//! A package can also contain a `lash.toml` at its root to set its own target directory, dotfiles
+//! mapping, rename rules, ignore patterns, install modes, conflict handling and description.
+//! These are merged over the other configuration files, but not the command line. A relative
+//! target is relative to the package.
+//! Subdirectories of a package can be linked into their own target directories with a `[targets]`
+//! table mapping each subdirectory to its target, e.g. `bin = "~/.local/bin"`.
+//! To see the supported options see [PackageConfig](crate::config::PackageConfig)
 //!
 //! Most options can also be specified on the commandline.
 //!
+//! # Ignoring files
+//!
+//! Files that belong to a package's repository or editor (`.git`, `*~`, top level `README*` and
+//! `LICENSE*` files etc.) are never linked. More paths can be ignored with a `.lashignore` file in
+//! the package, the `ignore` configuration option or `--ignore`. These all use the gitignore syntax
+//! so a pattern starting with `!` links a path that would otherwise be ignored.
+//!
+//! # Renaming files
+//!
+//! With `--dotfiles` (or `dotfiles = true`) a `dot-` prefix on the name of a file or directory in a
+//! package is replaced by `.` in the target directory, so `dot-bashrc` is linked as `.bashrc`. More
+//! renames can be added with the `rename` option of a configuration file or a package's
+//! `lash.toml`, e.g. `rename = [{ prefix = "dot_", replace = "." }, { suffix = ".tmpl" }]`. Each
+//! rule replaces a `prefix` or `suffix` of a name with `replace` (nothing by default), or the first
+//! match of a `regex` with `replace`. The rules are applied in order to each name in the path
+//! inside the package, never to the target directory.
+//!
+//! `lash add` reverses the rules to find the name a file should have in the package. Prefix and
+//! suffix rules are reversed automatically unless they remove part of the name, a regex rule needs
+//! a `reverse` table with its own `regex` and `replace`. When more than one rule could have given a
+//! name the last one is reversed. Adding fails if the name found wouldn't be renamed back to the
+//! same name.
+//!
+//! # Hooks
+//!
+//! Commands can be run when a package changes, e.g. to rebuild caches, with the `pre_link`,
+//! `post_link`, `pre_unlink` and `post_unlink` options in a `[hooks]` table of a configuration file
+//! or a package's `lash.toml`. They are run with `sh -c` from the package directory with
+//! `LASH_HOOK`, `LASH_PACKAGE`, `LASH_TARGET` and `LASH_PATHS` (the changed paths, one per line)
+//! set. If a pre hook fails the package isn't changed. Hooks are only printed with `--dry-run`.
+//!
+//! # Install modes
+//!
+//! Files are installed as symbolic links by default. For programs that don't follow symlinks, or
+//! files that have to be on another file system than the package, files can be installed as hard
+//! links or copies instead with `--mode hardlink`, `--mode copy` or the `mode` configuration
+//! option. A package's `lash.toml` can choose the mode of some of its files with a `[modes]` table
+//! mapping gitignore patterns to modes, e.g. `"*.service" = "copy"`.
+//!
+//! The store records a hash of each hard link or copy when it is installed. If the file is changed
+//! after that `lash status` reports it as `modified` and lash never replaces or removes it, so the
+//! changes aren't lost. Unchanged copies are updated when the package file changes. Directories
+//! containing hard links or copies are never folded.
+//!
+//! `lash diff` shows how the installed hard links and copies differ from the package files and
+//! `lash pull` copies the changes made to them back into the package. Files that changed in both
+//! places since they were installed are only pulled with `--force`.
+//!
+//! # Store
+//!
+//! Lash keeps a record of every file and directory it creates in a store. By default the store
+//! is kept in `$XDG_STATE_HOME/lash/`. A different directory can be used with the `--store` flag,
+//! the `LASH_STORE` environment variable or the `store` configuration option (in that order of
+//! priority). Separate named stores can be kept in the same directory with `--profile`,
+//! `LASH_PROFILE` or the `profile` configuration option.
+//!
+//! Files that are in the way of a link can be moved out of the way with `--on-conflict=backup`.
+//! They are kept in the `backups` directory next to the store. `lash unlink` asks whether to move
+//! them back when run in a terminal, `lash unlink --restore` moves them back without asking.
+//!
+//! Every change lash makes is recorded in a journal in the `journal` directory next to the store,
+//! together with copies of the files it overwrote or removed. `lash history` lists the recorded
+//! operations and `lash undo` reverses one. The journal is never pruned, deleting the directory
+//! forgets the history.
+//!
 //! # Compared to GNU Stow
 //!
 //! - Configured by TOML files called `lash.toml`
 //! - Commandline arguments do not match (`lash link` vs `stow -S`)
-//! - Does not fold any of the directory structure (lash always creates the folders and links
-//!   invididual files rather than attempting to minimize the number of links created).
+//! - Links are absolute by default. Relative links like the ones GNU Stow creates can be used with
+//!   `--link-style relative` or `link_style = "relative"` in a configuration file.
+//! - Does not fold any of the directory structure by default (lash creates the folders and links
+//!   invididual files rather than attempting to minimize the number of links created). Stow-like
+//!   folding can be turned on with `--fold` or `fold = true` in a configuration file.
 //! - The `--dotfiles` option has been fixed. None of the bugs that plague GNU Stow are a problem
 //!   in this implementation. I am aware some fixes had been made and are available in patches but
 //!   even then some bugs remained (try using `--dotfiles` and `--adopt` with GNU Stow and the
 //!   patches!).
 
-use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::Path;
 
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
@@ -48,13 +123,50 @@ use log::{debug, error, info, warn};
 use anyhow::{Result, anyhow};
 use simplelog::{ColorChoice, LevelFilter, TermLogger, TerminalMode};
 
-use crate::options::Options;
+use crate::options::{Command, Options};
+use crate::store::Store;
 
+mod add;
+mod adopt;
 mod cli;
 mod command;
 mod config;
+mod confirm;
+mod drift;
+mod eject;
+mod history;
+mod hooks;
+mod journal;
 mod link;
+mod mapping;
 mod options;
+mod plan;
+mod status;
+mod store;
+mod store_command;
+
+/// The store used to be kept in the user's data directory. If there is a store there and not in the
+/// default location then move it so the links it knows about are still managed.
+fn migrate_legacy_store(store: &Path) -> Result<()> {
+    let default_store = options::default_store_dir().join(options::STORE_FILE);
+    let Some(legacy_store) = dirs::data_dir().map(|dir| dir.join("lash/store.bin")) else {
+        return Ok(());
+    };
+
+    if store != default_store || store.exists() || !legacy_store.exists() {
+        return Ok(());
+    }
+
+    info!("Moving store from {:?} to {:?}", legacy_store, store);
+    if let Some(parent) = store.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // The directories may be on different file systems so rename can't be relied on
+    fs::copy(&legacy_store, store)?;
+    fs::remove_file(&legacy_store)?;
+
+    Ok(())
+}
 
 fn main() -> Result<()> {
     let options = Options::new()?;
@@ -73,55 +185,47 @@ fn main() -> Result<()> {
 
     debug!("{:?}", options);
 
-    let data_dir: PathBuf = [
-        #[cfg(debug_assertions)]
-        PathBuf::from("./"),
-        #[cfg(not(debug_assertions))]
-        dirs::data_dir().unwrap_or_else(|| PathBuf::from("~/.local/share/lash/")),
-        #[cfg(not(debug_assertions))]
-        "lash".into(),
-        "store.bin".into(),
-    ]
-    .iter()
-    .collect();
+    let data_dir = options.store.as_path();
+
+    if let Err(e) = migrate_legacy_store(data_dir) {
+        warn!("Failed to move the store from its old location: {:?}", e);
+    }
+
+    if let Command::Store(command) = &options.command {
+        return store_command::process_store_command(&options, command, data_dir);
+    }
 
     debug!("Loading store from {:?}", data_dir);
 
-    let mut store: HashMap<PathBuf, PathBuf> = {
-        if !data_dir.exists() {
-            debug!("Store does not exist, continuing");
-            if let Err(e) = std::fs::create_dir_all(data_dir.parent().unwrap()) {
-                error!(
-                    "Failed to create directory for store: {:?}",
-                    data_dir.parent()
-                );
-                return Err(e.into());
-            }
-            HashMap::new()
-        } else {
-            let mut file = match fs::File::open(&data_dir) {
-                Ok(file) => file,
-                Err(e) => {
-                    error!("Failed to read data from the store: {:?}", e);
-                    return Err(e.into());
-                }
-            };
-
-            let config = bincode::config::standard();
-            let store: HashMap<PathBuf, PathBuf> =
-                match bincode::decode_from_std_read(&mut file, config) {
-                    Ok(data) => data,
-                    Err(e) => {
-                        return Err(e.into());
-                    }
-                };
-            debug!("Store loaded");
-            store
+    let mut store = match Store::open(data_dir) {
+        Ok(store) => store,
+        Err(e) => {
+            error!("Failed to read data from the store: {:?}", e);
+            return Err(e);
         }
     };
+    debug!("Store loaded (version {})", store.version);
 
     debug!("Store contents: {:?}", store);
 
+    match &options.command {
+        Command::Add { paths } => {
+            return add::add(&options, &mut store, &options.packages[0], paths);
+        }
+        Command::Eject { paths } => return eject::eject(&options, &mut store, paths),
+        Command::Diff { paths } => return drift::diff(&store, paths),
+        Command::Pull { paths, force } => {
+            return drift::pull(&options, &mut store, paths, *force);
+        }
+        Command::Status => return status::status(&options, &store),
+        Command::History { limit } => return history::history(&store, *limit),
+        Command::Undo { id } => {
+            history::undo(&options, &mut store, *id)?;
+            return store.flush();
+        }
+        _ => {}
+    }
+
     let mut ret: Result<()> = Ok(());
     for res in command::process_packages(&options, &mut store) {
         match res {
@@ -133,16 +237,9 @@ fn main() -> Result<()> {
         }
     }
 
-    {
-        let mut file = match fs::File::create(&data_dir) {
-            Ok(file) => file,
-            Err(e) => {
-                error!("Failed to open the store to write data: {:?}", e);
-                return Err(e.into());
-            }
-        };
-        let config = bincode::config::standard();
-        bincode::encode_into_std_write(&store, &mut file, config)?;
+    if let Err(e) = store.flush() {
+        error!("Failed to write data to the store: {:?}", e);
+        return Err(e);
     }
 
     ret
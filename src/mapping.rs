@@ -0,0 +1,272 @@
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use anyhow::{Result, anyhow};
+use regex::Regex;
+use serde_derive::Deserialize;
+
+use crate::options::Options;
+
+/// A rule renaming the components of paths in a package, as written in the `rename` option of
+/// the configuration files.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum RenameRule {
+    /// Replace `prefix` at the start of a name with `replace`
+    Prefix {
+        prefix: String,
+        #[serde(default)]
+        replace: String,
+    },
+    /// Replace `suffix` at the end of a name with `replace`
+    Suffix {
+        suffix: String,
+        #[serde(default)]
+        replace: String,
+    },
+    /// Replace the first match of `regex` in a name with `replace`, which can refer to capture
+    /// groups with `$1` or `$name`. Regex renames can only be reversed with a `reverse` rule
+    Regex {
+        regex: String,
+        replace: String,
+        reverse: Option<RegexRename>,
+    },
+}
+
+/// A regex rename without a reverse, used to undo a [RenameRule::Regex].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RegexRename {
+    pub regex: String,
+    pub replace: String,
+}
+
+/// The rule `--dotfiles` adds in front of the configured rules.
+fn dotfiles_rule() -> RenameRule {
+    RenameRule::Prefix {
+        prefix: "dot-".to_string(),
+        replace: ".".to_string(),
+    }
+}
+
+/// A [RenameRule] ready to be applied.
+#[derive(Debug)]
+enum Rule {
+    Prefix {
+        prefix: String,
+        replace: String,
+    },
+    Suffix {
+        suffix: String,
+        replace: String,
+    },
+    Regex {
+        regex: Regex,
+        replace: String,
+        reverse: Option<(Regex, String)>,
+    },
+}
+
+impl Rule {
+    fn new(rule: &RenameRule) -> Result<Self> {
+        Ok(match rule {
+            RenameRule::Prefix { prefix, replace } => Rule::Prefix {
+                prefix: prefix.to_owned(),
+                replace: replace.to_owned(),
+            },
+            RenameRule::Suffix { suffix, replace } => Rule::Suffix {
+                suffix: suffix.to_owned(),
+                replace: replace.to_owned(),
+            },
+            RenameRule::Regex {
+                regex,
+                replace,
+                reverse,
+            } => Rule::Regex {
+                regex: Regex::new(regex)?,
+                replace: replace.to_owned(),
+                reverse: match reverse {
+                    Some(reverse) => {
+                        Some((Regex::new(&reverse.regex)?, reverse.replace.to_owned()))
+                    }
+                    None => None,
+                },
+            },
+        })
+    }
+
+    /// Rename a name from the package to its name in the target directory.
+    fn apply(&self, name: &str) -> String {
+        match self {
+            Rule::Prefix { prefix, replace } => match name.strip_prefix(prefix.as_str()) {
+                Some(rest) => format!("{}{}", replace, rest),
+                None => name.to_owned(),
+            },
+            Rule::Suffix { suffix, replace } => match name.strip_suffix(suffix.as_str()) {
+                Some(rest) => format!("{}{}", rest, replace),
+                None => name.to_owned(),
+            },
+            Rule::Regex { regex, replace, .. } => {
+                regex.replace(name, replace.as_str()).into_owned()
+            }
+        }
+    }
+
+    /// Rename a name in the target directory back to the name it would have in the package.
+    ///
+    /// Rules that remove part of the name can't tell which names they were applied to so leave
+    /// every name alone, as do regex rules without a reverse.
+    fn reverse(&self, name: &str) -> String {
+        match self {
+            Rule::Prefix { prefix, replace } if !replace.is_empty() => {
+                match name.strip_prefix(replace.as_str()) {
+                    Some(rest) => format!("{}{}", prefix, rest),
+                    None => name.to_owned(),
+                }
+            }
+            Rule::Suffix { suffix, replace } if !replace.is_empty() => {
+                match name.strip_suffix(replace.as_str()) {
+                    Some(rest) => format!("{}{}", rest, suffix),
+                    None => name.to_owned(),
+                }
+            }
+            Rule::Regex {
+                reverse: Some((regex, replace)),
+                ..
+            } => regex.replace(name, replace.as_str()).into_owned(),
+            _ => name.to_owned(),
+        }
+    }
+
+    /// Check if the rule could change `name` when applied or reversed. Names that aren't UTF-8
+    /// can only be left as they are, which is fine when no rule touches them.
+    fn touches(&self, name: &OsStr) -> bool {
+        let bytes = name.as_encoded_bytes();
+        match self {
+            Rule::Prefix { prefix, replace } => {
+                bytes.starts_with(prefix.as_bytes())
+                    || (!replace.is_empty() && bytes.starts_with(replace.as_bytes()))
+            }
+            Rule::Suffix { suffix, replace } => {
+                bytes.ends_with(suffix.as_bytes())
+                    || (!replace.is_empty() && bytes.ends_with(replace.as_bytes()))
+            }
+            Rule::Regex { regex, reverse, .. } => {
+                let name = name.to_string_lossy();
+                regex.is_match(&name) || reverse.as_ref().is_some_and(|(r, _)| r.is_match(&name))
+            }
+        }
+    }
+}
+
+/// The pipeline of [RenameRule]s mapping paths inside a package to paths inside the target
+/// directory.
+///
+/// Each rule is applied in order to every component of the path relative to the package, so the
+/// target directory itself and parts of names that don't match a rule are never changed.
+#[derive(Debug)]
+pub struct NameMapping {
+    rules: Vec<Rule>,
+}
+
+impl NameMapping {
+    /// Build the pipeline from `options.rename`, after the `dot-` rule when `options.dotfiles` is
+    /// set.
+    pub fn new(options: &Options) -> Result<Self> {
+        let dotfiles = options.dotfiles.then(dotfiles_rule);
+        let rules = dotfiles
+            .iter()
+            .chain(options.rename.iter())
+            .map(Rule::new)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { rules })
+    }
+
+    /// Map a path relative to the package to the path relative to the target directory.
+    pub fn map(&self, path: &Path) -> Result<PathBuf> {
+        path.components()
+            .map(|c| match c {
+                Component::Normal(name) if self.untouched(name) => Ok(PathBuf::from(name)),
+                Component::Normal(name) => {
+                    let mapped = self
+                        .rules
+                        .iter()
+                        .fold(to_str(name)?.to_owned(), |name, rule| rule.apply(&name));
+                    check_name(name, &mapped)
+                }
+                c => Err(anyhow!(
+                    "{:?} is not a path relative to the package ({:?})",
+                    path,
+                    c
+                )),
+            })
+            .collect()
+    }
+
+    /// Map a path relative to the target directory back to the path it would have relative to
+    /// the package, the reverse of [NameMapping::map].
+    ///
+    /// The rules are reversed last to first, so when more than one rule could have given a name
+    /// the last one wins. Fails if mapping the result again wouldn't give back `path` exactly,
+    /// e.g. because the rules can't be reversed.
+    pub fn unmap(&self, path: &Path) -> Result<PathBuf> {
+        path.components()
+            .map(|c| match c {
+                Component::Normal(name) if self.untouched(name) => Ok(PathBuf::from(name)),
+                Component::Normal(name) => {
+                    let name = to_str(name)?;
+                    let unmapped = self
+                        .rules
+                        .iter()
+                        .rev()
+                        .fold(name.to_owned(), |name, rule| rule.reverse(&name));
+                    let unmapped = check_name(name.as_ref(), &unmapped)?;
+
+                    let remapped = self.map(&unmapped)?;
+                    match remapped.as_os_str() == name {
+                        true => Ok(unmapped),
+                        false => Err(anyhow!(
+                            "No name in the package is renamed to {:?}, the rename rules can't be \
+                             reversed ({:?} would be renamed to {:?})",
+                            name,
+                            unmapped,
+                            remapped
+                        )),
+                    }
+                }
+                c => Err(anyhow!(
+                    "{:?} is not a path relative to the target directory ({:?})",
+                    path,
+                    c
+                )),
+            })
+            .collect()
+    }
+
+    /// Check if `name` can be used as it is because it isn't UTF-8 and no rule would change it.
+    fn untouched(&self, name: &OsStr) -> bool {
+        name.to_str().is_none() && !self.rules.iter().any(|r| r.touches(name))
+    }
+}
+
+fn to_str(name: &OsStr) -> Result<&str> {
+    name.to_str().ok_or(anyhow!(
+        "{:?} couldn't be converted to a str. Is it UTF-8?",
+        name
+    ))
+}
+
+/// Check that renaming `name` left a single path component.
+fn check_name(name: &OsStr, renamed: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(renamed);
+    match path.components().collect::<Vec<_>>().as_slice() {
+        [Component::Normal(n)] if *n == renamed => Ok(path),
+        _ => Err(anyhow!(
+            "{:?} was renamed to {:?}, which is not a valid name",
+            name,
+            renamed
+        )),
+    }
+}
@@ -1,40 +1,118 @@
 use std::borrow::Borrow;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Component, Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use clap::Parser;
+use path_absolutize::Absolutize;
 
-use crate::cli::Cli;
-use crate::config::Config;
+use crate::cli::{AdoptStrategy, Cli, ConflictPolicy, InstallMode, LinkStyle, StoreCommand};
+use crate::config::{AdoptConfig, Config, Hooks, PackageConfig};
+use crate::mapping::RenameRule;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Command {
     /// Install packages
     Link,
     /// Remove packages
     Unlink,
+    /// Update installed packages
+    Relink,
+    /// Move paths into a package
+    Add { paths: Vec<PathBuf> },
+    /// Replace managed links with copies of the package files
+    Eject { paths: Vec<PathBuf> },
+    /// Show how installed copies differ from the package files
+    Diff { paths: Vec<PathBuf> },
+    /// Copy changes to installed copies back into the packages
+    Pull { paths: Vec<PathBuf>, force: bool },
+    /// Show the install state of packages
+    Status,
+    /// List the operations in the journal
+    History { limit: Option<usize> },
+    /// Reverse an operation from the journal
+    Undo { id: Option<u64> },
+    /// Inspect or edit the store
+    Store(StoreCommand),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 /// Struct containing the finalised options for the command.
 ///
 /// All of the options have to be filled in by this point.
 pub struct Options {
     /// Map "dot-" at start of directory names in source directory to "." in target names.
     pub dotfiles: bool,
+    /// Rules renaming the paths in packages, from the configuration files
+    pub rename: Vec<RenameRule>,
+    /// Link whole directories instead of each file inside them where no other package uses them
+    pub fold: bool,
+    /// Patterns for paths in packages that shouldn't be linked, from the configuration files then
+    /// the command line
+    pub ignore: Vec<String>,
     /// Do not change any files.
     pub dry_run: bool,
+    /// Don't ask before adopting or overwriting files.
+    pub yes: bool,
     /// Print more information about the files being processed.
     pub verbose: bool,
+    /// Whether links are created with absolute or relative destinations
+    pub link_style: LinkStyle,
+    /// Whether files are installed as symlinks, hard links or copies
+    pub mode: InstallMode,
+    /// Install modes for the paths in the package matching each pattern, from its configuration
+    /// file
+    pub modes: BTreeMap<String, InstallMode>,
     /// Target directory to create links to package in. Defaults to parent of current directory
     pub target: PathBuf,
     /// The selected command
     pub command: Command,
-    /// "Adopt" files already existing on the file system into the package. This is done by
-    /// replacing the source file with the existing file. The link is still created as normal.
-    pub adopt: bool,
+    /// What to do when a file that isn't managed by lash is in the way of a link
+    pub on_conflict: ConflictPolicy,
+    /// Which version of a file to keep when adopting it
+    pub adopt: AdoptStrategy,
+    /// Move the paths that were backed up when linking back when unlinking
+    pub restore: bool,
     /// List of packages to install/remove
     pub packages: Vec<PathBuf>,
+    /// The file the store is kept in
+    pub store: PathBuf,
+    /// Description of the package from its configuration file
+    pub description: Option<String>,
+    /// Target directories for subdirectories of the package from its configuration file
+    pub targets: BTreeMap<PathBuf, PathBuf>,
+    /// Commands to run before and after packages are linked or unlinked
+    pub hooks: Hooks,
+    /// The command line and configuration files the options were merged from, kept so they can be
+    /// merged again with a package's configuration
+    cli: Cli,
+    config: Config,
+}
+
+/// Name of the directory next to the store file that displaced paths are backed up to
+pub const BACKUP_DIR: &str = "backups";
+
+/// Name of the store file inside the store directory (or profile directory)
+pub const STORE_FILE: &str = "store.bin";
+
+/// The directory the store is kept in when no other location is configured.
+///
+/// Follows the XDG Base Directory specification and uses `$XDG_STATE_HOME/lash/`.
+pub fn default_store_dir() -> PathBuf {
+    dirs::state_dir()
+        .or_else(|| dirs::home_dir().map(|home| home.join(".local/state")))
+        .expect("Could not get home dir!")
+        .join("lash")
+}
+
+/// Perform shell expansion on a path.
+fn expand_path(path: &Path) -> Result<PathBuf> {
+    let expanded = shellexpand::full(path.to_str().ok_or(anyhow!(
+        "{:?} couldn't be converted to a str. Is it UTF-8?",
+        path
+    ))?)?;
+    Ok(expanded.into_owned().into())
 }
 
 impl Options {
@@ -48,18 +126,78 @@ impl Options {
         Self::merge(cli_options.borrow(), config_options.borrow())
     }
 
+    /// The link style set on the command line or in a configuration file, `None` when the default
+    /// is used.
+    pub fn chosen_link_style(&self) -> Option<LinkStyle> {
+        self.cli.link_style.or(self.config.link_style)
+    }
+
+    /// Whether the install mode was set on the command line or in a configuration file, rather
+    /// than every path using the default.
+    pub fn chosen_mode(&self) -> bool {
+        self.cli.mode.is_some() || self.config.mode.is_some() || !self.modes.is_empty()
+    }
+
+    /// Get the options to process `package` with by merging the package's configuration file
+    /// over the configuration files. The command line still takes priority.
+    pub fn for_package(&self, package: &Path) -> Result<Self> {
+        let mut package_config = PackageConfig::new(package)?;
+        if let Some(target) = &package_config.target {
+            package_config.target = Some(package.join(expand_path(target)?));
+        }
+
+        let mut options = Self::merge(&self.cli, &self.config.with_package(&package_config))?;
+        options.description = package_config.description;
+        options.modes = package_config.modes.unwrap_or_default();
+
+        for (subtree, target) in package_config.targets.iter().flatten() {
+            if !subtree
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)))
+                || subtree.as_os_str().is_empty()
+            {
+                return Err(anyhow!(
+                    "{:?} in the targets of package {:?} is not a directory inside the package",
+                    subtree,
+                    package
+                ));
+            }
+            let target = package
+                .join(expand_path(target)?)
+                .absolutize()?
+                .into_owned();
+            options.targets.insert(subtree.to_owned(), target);
+        }
+
+        Ok(options)
+    }
+
     /// Merge the options from the command line and the configuration files. All of the potential
     /// options need to have a value.
     fn merge(cli: &Cli, config: &Config) -> Result<Self> {
         let dotfiles = config.dotfiles.unwrap_or(false) | cli.dotfiles;
         let verbose = config.verbose.unwrap_or(false) | cli.verbose;
-        // TODO: Why does the options enum *have* to contain a value for adopt when it's only used
-        // for some operations?
-        let adopt = config.adopt.unwrap_or(false)
-            | match cli.command {
-                crate::cli::Command::Link { adopt, .. } => adopt,
-                crate::cli::Command::Unlink { .. } => false,
-            };
+        // TODO: Why does the options enum *have* to contain a value for on_conflict when it's only
+        // used for some operations?
+        // --adopt and adopt in the configuration files are shorthands for the adopt policy
+        let (cli_adopt, cli_on_conflict) = match cli.command {
+            crate::cli::Command::Link { conflicts, .. }
+            | crate::cli::Command::Relink { conflicts, .. } => {
+                (conflicts.adopt, conflicts.on_conflict)
+            }
+            _ => (None, None),
+        };
+        let config_adopt = match config.adopt {
+            Some(AdoptConfig::Enabled(true)) => Some(AdoptStrategy::Target),
+            Some(AdoptConfig::Strategy(strategy)) => Some(strategy),
+            Some(AdoptConfig::Enabled(false)) | None => None,
+        };
+        let on_conflict = cli_on_conflict
+            .or(cli_adopt.map(|_| ConflictPolicy::Adopt))
+            .or(config.on_conflict)
+            .or(config_adopt.map(|_| ConflictPolicy::Adopt))
+            .unwrap_or(ConflictPolicy::Fail);
+        let adopt = cli_adopt.or(config_adopt).unwrap_or(AdoptStrategy::Target);
 
         let mut raw_target = cli.target.to_owned().or(config.target.to_owned());
         let raw_target =
@@ -70,20 +208,93 @@ impl Options {
                 .expect("Target couldn't be converted to a str. Is it UTF-8?"),
         )?;
 
+        // Command line options take priority over the environment which takes priority over the
+        // configuration files
+        let store_dir = cli
+            .store
+            .to_owned()
+            .or(env::var_os("LASH_STORE").map(PathBuf::from))
+            .or(config.store.to_owned())
+            .unwrap_or_else(default_store_dir);
+        let profile = cli
+            .profile
+            .to_owned()
+            .or(env::var("LASH_PROFILE").ok())
+            .or(config.profile.to_owned());
+
+        let mut store = expand_path(&store_dir)?;
+        if let Some(profile) = profile {
+            if profile.is_empty() || profile.contains(std::path::is_separator) {
+                return Err(anyhow!("Invalid store profile name {:?}", profile));
+            }
+            store.push("profiles");
+            store.push(profile);
+        }
+        store.push(STORE_FILE);
+
         Ok(Self {
             dotfiles,
+            rename: config.rename.to_owned().unwrap_or_default(),
+            fold: config.fold.unwrap_or(false) | cli.fold,
+            ignore: config
+                .ignore
+                .iter()
+                .flatten()
+                .chain(cli.ignore.iter())
+                .cloned()
+                .collect(),
             dry_run: cli.dry_run,
+            yes: cli.yes,
             verbose,
+            link_style: cli
+                .link_style
+                .or(config.link_style)
+                .unwrap_or(LinkStyle::Absolute),
+            mode: cli.mode.or(config.mode).unwrap_or(InstallMode::Symlink),
+            modes: BTreeMap::new(),
             target: target.into_owned().into(),
-            command: match cli.command {
+            command: match &cli.command {
                 crate::cli::Command::Link { .. } => Command::Link,
                 crate::cli::Command::Unlink { .. } => Command::Unlink,
+                crate::cli::Command::Relink { .. } => Command::Relink,
+                crate::cli::Command::Add { paths, .. } => Command::Add {
+                    paths: paths.to_owned(),
+                },
+                crate::cli::Command::Eject { paths } => Command::Eject {
+                    paths: paths.to_owned(),
+                },
+                crate::cli::Command::Diff { paths } => Command::Diff {
+                    paths: paths.to_owned(),
+                },
+                crate::cli::Command::Pull { paths, force } => Command::Pull {
+                    paths: paths.to_owned(),
+                    force: *force,
+                },
+                crate::cli::Command::Status { .. } => Command::Status,
+                crate::cli::Command::History { limit } => Command::History { limit: *limit },
+                crate::cli::Command::Undo { id } => Command::Undo { id: *id },
+                crate::cli::Command::Store { command } => Command::Store(command.to_owned()),
             },
+            on_conflict,
             adopt,
+            restore: matches!(
+                cli.command,
+                crate::cli::Command::Unlink { restore: true, .. }
+            ),
             packages: match &cli.command {
                 crate::cli::Command::Link { packages, .. } => packages.to_owned(),
-                crate::cli::Command::Unlink { packages } => packages.to_owned(),
+                crate::cli::Command::Unlink { packages, .. } => packages.to_owned(),
+                crate::cli::Command::Relink { packages, .. } => packages.to_owned(),
+                crate::cli::Command::Add { package, .. } => vec![package.to_owned()],
+                crate::cli::Command::Status { packages } => packages.to_owned(),
+                _ => Vec::new(),
             },
+            store,
+            description: None,
+            targets: BTreeMap::new(),
+            hooks: config.hooks.to_owned(),
+            cli: cli.to_owned(),
+            config: config.to_owned(),
         })
     }
 }
@@ -0,0 +1,563 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use anyhow::{Result, anyhow};
+use serde_derive::Serialize;
+
+use crate::adopt;
+use crate::cli::{ConflictPolicy, InstallMode, LinkStyle};
+use crate::confirm::Confirm;
+use crate::link::Link;
+use crate::options::{BACKUP_DIR, Options};
+use crate::store::{Entry, EntryKind, Store};
+
+/// A single step needed to bring the target directory in line with a package.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Create the missing directory at the link target
+    CreateDir { link: Link },
+    /// Record a directory that already exists at the link target as managed
+    ManageDir { link: Link },
+    /// Create a symlink, hard link or copy at the link target depending on its install mode
+    CreateLink { link: Link },
+    /// Record a symlink, hard link or copy of the right file as managed
+    KeepLink { link: Link },
+    /// Replace a symlink to the right file written in the other link style, or a path installed
+    /// from the right file in another install mode or from an older version of it
+    Relink { link: Link },
+    /// Replace the package file with the file at the link target and then link it
+    Adopt { link: Link },
+    /// Move a conflicting path into the backup area and create the link (or directory) in its
+    /// place
+    Backup { link: Link, backup: PathBuf },
+    /// Adopt the target or keep the package file and back up the target, whichever the user
+    /// chooses
+    Choose { link: Link, backup: PathBuf },
+    /// Move a path displaced by a link back from the backup area
+    Restore { target: PathBuf, backup: PathBuf },
+    /// Restore a path displaced by a link or leave it in the backup area, whichever the user
+    /// chooses
+    OfferRestore { target: PathBuf, backup: PathBuf },
+    /// Remove a conflicting file or symlink so it can be replaced by a link to `source`
+    Overwrite { target: PathBuf, source: PathBuf },
+    /// Move a path that isn't in the package yet into the package and link it back
+    Add { link: Link },
+    /// Remove a symlink pointing to `source`
+    RemoveLink { target: PathBuf, source: PathBuf },
+    /// Remove a hard link or copy of `source` that hasn't been changed since it was installed
+    RemoveCopy { target: PathBuf, source: PathBuf },
+    /// Remove an empty directory
+    RemoveDir { target: PathBuf },
+    /// Replace a folded directory (a link to `source`, a directory in another package) with a
+    /// directory containing links to each path inside `source`
+    Unfold { target: PathBuf, source: PathBuf },
+    /// Replace a directory containing only links to the paths inside `source` with a link to
+    /// `source`
+    Refold { target: PathBuf, source: PathBuf },
+    /// Remove a store entry without changing the file system
+    Forget { target: PathBuf },
+}
+
+impl Action {
+    /// Adopt the target of `link` if it is newer than the package file, otherwise keep the
+    /// package file and move the target to `backup`.
+    pub fn keep_newer(link: &Link, backup: &Path) -> Result<Action> {
+        Ok(match adopt::target_is_newer(link)? {
+            true => Action::Adopt { link: link.clone() },
+            false => Action::Backup {
+                link: link.clone(),
+                backup: backup.to_owned(),
+            },
+        })
+    }
+
+    /// Actions that throw away the contents of a file that lash doesn't have a copy of.
+    pub fn is_destructive(&self) -> bool {
+        matches!(self, Action::Adopt { .. } | Action::Overwrite { .. })
+    }
+
+    /// The path in the target directory that is created, replaced or removed by the action.
+    ///
+    /// Actions that only update the store have no path, neither does [Action::Overwrite] as the
+    /// path is replaced by the action following it.
+    pub fn changed_path(&self) -> Option<&Path> {
+        match self {
+            Action::CreateDir { link }
+            | Action::CreateLink { link }
+            | Action::Relink { link }
+            | Action::Adopt { link }
+            | Action::Backup { link, .. }
+            | Action::Choose { link, .. }
+            | Action::Add { link } => Some(&link.target),
+            Action::Restore { target, .. }
+            | Action::OfferRestore { target, .. }
+            | Action::Unfold { target, .. }
+            | Action::Refold { target, .. }
+            | Action::RemoveLink { target, .. }
+            | Action::RemoveCopy { target, .. }
+            | Action::RemoveDir { target } => Some(target),
+            Action::ManageDir { .. }
+            | Action::KeepLink { .. }
+            | Action::Overwrite { .. }
+            | Action::Forget { .. } => None,
+        }
+    }
+
+    /// Actions that only update the store are less interesting so are only logged when verbose.
+    fn log_level(&self) -> log::Level {
+        match self {
+            Action::ManageDir { .. } | Action::KeepLink { .. } | Action::Forget { .. } => {
+                log::Level::Debug
+            }
+            _ => log::Level::Info,
+        }
+    }
+
+    /// Perform the action. The plan's package and target are needed to create store entries.
+    fn apply(&self, plan: &Plan, options: &Options, store: &mut Store) -> Result<()> {
+        // Keep track of the backup of whatever the entry displaced when it is recreated
+        let entry = |store: &Store, link: &Link, kind| {
+            let mut entry = Entry::new(&link.source, &plan.package, &plan.target, kind, options);
+            entry.backup = store.get(&link.target).and_then(|e| e.backup.to_owned());
+            entry.with_hash()
+        };
+
+        match self {
+            Action::CreateDir { link } => {
+                store.journal.create_dir_all(&link.target)?;
+                store.insert(
+                    link.target.to_owned(),
+                    entry(store, link, EntryKind::Directory)?,
+                );
+            }
+            Action::ManageDir { link } => {
+                store.insert(
+                    link.target.to_owned(),
+                    entry(store, link, EntryKind::Directory)?,
+                );
+            }
+            Action::CreateLink { link } => {
+                let kind = install(store, link, options.link_style)?;
+                store.insert(link.target.to_owned(), entry(store, link, kind)?);
+            }
+            Action::KeepLink { link } => {
+                store.insert(link.target.to_owned(), entry(store, link, link_kind(link))?);
+            }
+            Action::Relink { link } => {
+                store.journal.remove_file(&link.target)?;
+                let kind = install(store, link, options.link_style)?;
+                store.insert(link.target.to_owned(), entry(store, link, kind)?);
+            }
+            Action::Unfold { target, source } => {
+                // The new entries belong to the package the folded directory belongs to
+                let folded = store
+                    .get(target)
+                    .cloned()
+                    .ok_or(anyhow!("{:?} is not a folded directory", target))?;
+
+                store.journal.remove_file(target)?;
+                store.journal.create_dir_all(target)?;
+                store.insert(
+                    target.to_owned(),
+                    Entry {
+                        kind: EntryKind::Directory,
+                        ..folded.clone()
+                    },
+                );
+
+                let mut paths = source
+                    .read_dir()?
+                    .map(|e| e.map(|e| e.path()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                paths.sort();
+                for path in paths {
+                    let link = Link {
+                        target: target.join(path.file_name().unwrap_or_default()),
+                        source: path,
+                        mode: InstallMode::Symlink,
+                    };
+                    store
+                        .journal
+                        .symlink(&link.dest(options.link_style)?, &link.target)?;
+                    let kind = link_kind(&link);
+                    store.insert(
+                        link.target,
+                        Entry {
+                            source: link.source,
+                            kind,
+                            backup: None,
+                            ..folded.clone()
+                        },
+                    );
+                }
+            }
+            Action::Refold { target, source } => {
+                let mut paths = target
+                    .read_dir()?
+                    .map(|e| e.map(|e| e.path()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                paths.sort();
+
+                let mut folded = None;
+                for path in paths {
+                    store.journal.remove_file(&path)?;
+                    folded = store.remove(&path).or(folded);
+                }
+                let folded = folded.ok_or(anyhow!("{:?} is empty", target))?;
+
+                store.journal.remove_dir(target)?;
+                let link = Link {
+                    source: source.to_owned(),
+                    target: target.to_owned(),
+                    mode: InstallMode::Symlink,
+                };
+                store
+                    .journal
+                    .symlink(&link.dest(options.link_style)?, target)?;
+                store.insert(
+                    target.to_owned(),
+                    Entry {
+                        source: source.to_owned(),
+                        kind: EntryKind::Folded,
+                        backup: None,
+                        ..folded
+                    },
+                );
+            }
+            Action::Adopt { link } => adopt::adopt(plan, options, store, link)?,
+            Action::Add { link } => {
+                if let Some(parent) = link.source.parent() {
+                    store.journal.create_dir_all(parent)?;
+                }
+                adopt::adopt(plan, options, store, link)?;
+            }
+            Action::Backup { link, backup } => {
+                if let Some(parent) = backup.parent() {
+                    store.journal.create_dir_all(parent)?;
+                }
+                store.journal.rename(&link.target, backup)?;
+                let kind = match link.source.is_dir() {
+                    true => {
+                        store.journal.create_dir_all(&link.target)?;
+                        EntryKind::Directory
+                    }
+                    false => install(store, link, options.link_style)?,
+                };
+                let mut entry = entry(store, link, kind)?;
+                entry.backup = Some(backup.to_owned());
+                store.insert(link.target.to_owned(), entry);
+            }
+            Action::Restore { target, backup } => {
+                store.journal.rename(backup, target)?;
+
+                // Remove the directories left empty in the backup area, up to the one for the
+                // operation that made the backup
+                let backups = options.store.with_file_name(BACKUP_DIR);
+                for dir in backup
+                    .ancestors()
+                    .skip(1)
+                    .take_while(|dir| dir.starts_with(&backups) && *dir != backups)
+                {
+                    if !dir.read_dir().is_ok_and(|mut c| c.next().is_none()) {
+                        break;
+                    }
+                    store.journal.remove_dir(dir)?;
+                }
+            }
+            Action::OfferRestore { target, backup } => {
+                // Nobody could be asked so leave the backup where it is
+                info!(
+                    "{:?} was backed up to {:?}, use --restore to restore it",
+                    target, backup
+                );
+            }
+            Action::Choose { link, backup } => {
+                // Nobody could be asked so keep the newer version
+                Action::keep_newer(link, backup)?.apply(plan, options, store)?;
+            }
+            Action::Overwrite { target, .. } => {
+                store.journal.remove_file(target)?;
+            }
+            Action::RemoveLink { target, .. } | Action::RemoveCopy { target, .. } => {
+                store.journal.remove_file(target)?;
+                store.remove(target);
+            }
+            Action::RemoveDir { target } => {
+                store.journal.remove_dir(target)?;
+                store.remove(target);
+            }
+            Action::Forget { target } => {
+                store.remove(target);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The kind of entry for a link, links to directories are folded directories.
+fn link_kind(link: &Link) -> EntryKind {
+    match (link.mode, link.source.is_dir()) {
+        (_, true) => EntryKind::Folded,
+        (InstallMode::Symlink, false) => EntryKind::Link,
+        (InstallMode::Hardlink, false) => EntryKind::Hardlink,
+        (InstallMode::Copy, false) => EntryKind::Copy,
+    }
+}
+
+/// Create the symlink (written in `style`), hard link or copy of the source at the target of
+/// `link` and return the kind of entry to record for it.
+///
+/// Copies get the permissions, timestamps and extended attributes of the package file.
+pub fn install(store: &mut Store, link: &Link, style: LinkStyle) -> Result<EntryKind> {
+    match link.mode {
+        InstallMode::Hardlink if !link.source.is_dir() => {
+            store.journal.hard_link(&link.source, &link.target)?;
+        }
+        InstallMode::Copy if !link.source.is_dir() => {
+            store.journal.copy(&link.source, &link.target)?;
+            adopt::copy_metadata(&link.source, &link.target)?;
+        }
+        _ => store.journal.symlink(&link.dest(style)?, &link.target)?,
+    }
+    Ok(link_kind(link))
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::CreateDir { link } => write!(f, "Create directory {:?}", link.target),
+            Action::ManageDir { link } => write!(f, "Manage directory {:?}", link.target),
+            Action::CreateLink { link } => match link.mode {
+                InstallMode::Hardlink => {
+                    write!(f, "Create hard link {:?} to {:?}", link.target, link.source)
+                }
+                InstallMode::Copy => write!(f, "Copy {:?} to {:?}", link.source, link.target),
+                InstallMode::Symlink => {
+                    write!(f, "Create link {:?} -> {:?}", link.target, link.source)
+                }
+            },
+            Action::KeepLink { link } => {
+                write!(f, "Keep link {:?} -> {:?}", link.target, link.source)
+            }
+            Action::Relink { link } => match link.mode {
+                InstallMode::Hardlink => write!(
+                    f,
+                    "Replace {:?} with a hard link to {:?}",
+                    link.target, link.source
+                ),
+                InstallMode::Copy => {
+                    write!(
+                        f,
+                        "Replace {:?} with a copy of {:?}",
+                        link.target, link.source
+                    )
+                }
+                InstallMode::Symlink => {
+                    write!(f, "Rewrite link {:?} -> {:?}", link.target, link.source)
+                }
+            },
+            Action::Adopt { link } => write!(
+                f,
+                "Adopt {:?} into the package and link it to {:?}",
+                link.target, link.source
+            ),
+            Action::Backup { link, backup } => match link.source.is_dir() {
+                true => write!(
+                    f,
+                    "Move {:?} to {:?} and create a directory in its place",
+                    link.target, backup
+                ),
+                false => write!(
+                    f,
+                    "Move {:?} to {:?} and link it to {:?}",
+                    link.target, backup, link.source
+                ),
+            },
+            Action::Restore { target, backup } => {
+                write!(f, "Restore {:?} from {:?}", target, backup)
+            }
+            Action::OfferRestore { target, backup } => {
+                write!(f, "Offer to restore {:?} from {:?}", target, backup)
+            }
+            Action::Choose { link, .. } => write!(
+                f,
+                "Choose whether to adopt {:?} or keep {:?}",
+                link.target, link.source
+            ),
+            Action::Overwrite { target, .. } => write!(f, "Overwrite {:?}", target),
+            Action::Add { link } => write!(
+                f,
+                "Add {:?} to the package as {:?} and link it back",
+                link.target, link.source
+            ),
+            Action::RemoveLink { target, source } => {
+                write!(f, "Remove link {:?} -> {:?}", target, source)
+            }
+            Action::RemoveCopy { target, source } => {
+                write!(f, "Remove {:?} (a copy of {:?})", target, source)
+            }
+            Action::RemoveDir { target } => write!(f, "Remove directory {:?}", target),
+            Action::Unfold { target, source } => write!(
+                f,
+                "Replace folded directory {:?} -> {:?} with a directory of links",
+                target, source
+            ),
+            Action::Refold { target, source } => {
+                write!(f, "Fold directory {:?} into a link to {:?}", target, source)
+            }
+            Action::Forget { target } => write!(f, "Forget {:?}", target),
+        }
+    }
+}
+
+/// What is in the way of a link.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ConflictKind {
+    /// A regular file
+    File,
+    /// A directory where a file needs to be linked
+    Directory,
+    /// A symlink pointing somewhere other than the package
+    Symlink { dest: PathBuf },
+    /// A FIFO, socket or device node
+    Special,
+}
+
+/// A path in the target directory that lash doesn't manage which is in the way of a link.
+#[derive(Debug, Clone, Serialize)]
+pub struct Conflict {
+    pub link: Link,
+    pub kind: ConflictKind,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let target = &self.link.target;
+        match &self.kind {
+            ConflictKind::File => write!(f, "{:?} is a file", target),
+            ConflictKind::Directory => write!(f, "{:?} is a directory", target),
+            ConflictKind::Symlink { dest } => write!(f, "{:?} is a link to {:?}", target, dest),
+            ConflictKind::Special => write!(f, "{:?} is a special file", target),
+        }
+    }
+}
+
+/// The actions needed to install or remove a package, computed before anything is changed.
+///
+/// A dry run prints the plan instead of executing it so what is printed is exactly what would be
+/// done.
+#[derive(Debug, Clone, Serialize)]
+pub struct Plan {
+    /// Canonical path to the package
+    pub package: PathBuf,
+    /// Absolute path to the target directory
+    pub target: PathBuf,
+    /// The actions to perform, in order
+    pub actions: Vec<Action>,
+    /// Paths that were in the way of the package's links
+    pub conflicts: Vec<Conflict>,
+}
+
+impl Plan {
+    /// Report the conflicts found while planning. Fails if the policy is to fail on conflicts so
+    /// nothing in the package is changed.
+    pub fn check_conflicts(&self, policy: ConflictPolicy) -> Result<()> {
+        let resolution = match policy {
+            ConflictPolicy::Fail => "",
+            ConflictPolicy::Skip => ", skipping",
+            ConflictPolicy::Backup => ", backing it up",
+            ConflictPolicy::Overwrite => ", overwriting it",
+            ConflictPolicy::Adopt => ", adopting it",
+        };
+
+        for conflict in self.conflicts.iter() {
+            match policy {
+                ConflictPolicy::Fail => error!("Conflict: {}", conflict),
+                _ => warn!("Conflict: {}{}", conflict, resolution),
+            }
+        }
+
+        match policy {
+            ConflictPolicy::Fail if !self.conflicts.is_empty() => Err(anyhow!(
+                "{} conflicting paths in the target directory, nothing was changed. Use \
+                 --on-conflict to choose how to resolve them",
+                self.conflicts.len()
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Ask whether to perform each of the destructive actions in the plan, removing the ones that
+    /// are declined along with the link that would have replaced the file.
+    pub fn confirm(&mut self, confirm: &mut Confirm) -> Result<()> {
+        let mut declined: Vec<PathBuf> = Vec::new();
+        let mut actions = Vec::new();
+
+        for action in self.actions.drain(..) {
+            if let Action::Choose { link, backup } = &action {
+                match confirm.choose(link, backup)? {
+                    Some(action) => actions.push(action),
+                    None => {
+                        info!("Leaving {:?} alone", link.target);
+                        declined.push(link.target.to_owned());
+                    }
+                }
+                continue;
+            }
+            if let Action::OfferRestore { target, backup } = &action {
+                match confirm.restore(target, backup)? {
+                    true => actions.push(Action::Restore {
+                        target: target.to_owned(),
+                        backup: backup.to_owned(),
+                    }),
+                    false => info!("Leaving the backup {:?} of {:?}", backup, target),
+                }
+                continue;
+            }
+
+            let target = match &action {
+                Action::Adopt { link } => &link.target,
+                Action::Overwrite { target, .. } => target,
+                Action::CreateLink { link } | Action::CreateDir { link }
+                    if declined.contains(&link.target) =>
+                {
+                    debug!("Not creating {:?}", link.target);
+                    continue;
+                }
+                _ => {
+                    actions.push(action);
+                    continue;
+                }
+            };
+
+            match confirm.confirm(&action)? {
+                true => actions.push(action),
+                false => {
+                    info!("Leaving {:?} alone", target);
+                    declined.push(target.to_owned());
+                }
+            }
+        }
+
+        self.actions = actions;
+        Ok(())
+    }
+
+    /// Perform each of the actions in order, stopping at the first failure. In dry run mode the
+    /// actions are only printed.
+    pub fn execute(&self, options: &Options, store: &mut Store) -> Result<()> {
+        for action in self.actions.iter() {
+            log::log!(action.log_level(), "{}", action);
+            if !options.dry_run {
+                action.apply(self, options, store)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use anyhow::{Result, anyhow};
+use path_absolutize::Absolutize;
+
+use crate::cli::{InstallMode, LinkStyle};
+use crate::command::get_paths;
+use crate::link::{Link, has_style};
+use crate::options::Options;
+use crate::store::{Entry, EntryKind, Store, same_file};
+
+/// The install state of a single path belonging to a package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum State {
+    /// The path is installed and points to the right file
+    Linked,
+    /// The path was installed but has since been removed from the target directory
+    Missing,
+    /// A file, directory or symlink that lash didn't create is in the way
+    Conflict,
+    /// The path was installed but the file no longer exists in the package
+    Zombie,
+    /// The path is a relative link to the right file and will be replaced by an absolute link
+    Relative,
+    /// The path is an absolute link to the right file and will be replaced by a relative link
+    Absolute,
+    /// The path was installed from the right file but in another install mode, or is a copy of
+    /// an older version of the file, and will be replaced
+    Outdated,
+    /// The path is a hard link or copy that has been changed since it was installed, it won't be
+    /// replaced or removed
+    Modified,
+    /// The file was added to the package since it was last installed
+    New,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            State::Linked => "linked",
+            State::Missing => "missing",
+            State::Conflict => "conflict",
+            State::Zombie => "zombie",
+            State::Relative => "relative",
+            State::Absolute => "absolute",
+            State::Outdated => "outdated",
+            State::Modified => "modified",
+            State::New => "new",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Work out the state of the target of a [Link] for a package file or directory.
+fn link_state(store: &Store, link: &Link, style: LinkStyle) -> Result<State> {
+    let target = &link.target;
+    let exists = target.exists() || target.is_symlink();
+
+    if !exists {
+        return Ok(match store.contains_key(target) {
+            true => State::Missing,
+            false => State::New,
+        });
+    }
+
+    if link.source.is_dir() && !target.is_symlink() {
+        return Ok(match target.is_dir() {
+            true => State::Linked,
+            false => State::Conflict,
+        });
+    }
+
+    if let Some(entry) = store.get(target)
+        && matches!(entry.kind, EntryKind::Hardlink | EntryKind::Copy)
+        && entry.source == link.source
+        && !target.is_symlink()
+    {
+        return Ok(match entry.is_modified(target)? {
+            true => State::Modified,
+            false if entry.is_current(target, link.mode)? => State::Linked,
+            false => State::Outdated,
+        });
+    }
+
+    if link.mode == InstallMode::Hardlink && !target.is_symlink() && same_file(target, &link.source)
+    {
+        return Ok(State::Linked);
+    }
+
+    if !target.is_symlink() || target.canonicalize().ok().as_ref() != Some(&link.source) {
+        return Ok(State::Conflict);
+    }
+
+    if link.mode != InstallMode::Symlink && !link.source.is_dir() {
+        return Ok(State::Outdated);
+    }
+
+    let dest = target.read_link()?;
+    Ok(match style {
+        _ if has_style(&dest, style)? => State::Linked,
+        LinkStyle::Absolute => State::Relative,
+        LinkStyle::Relative => State::Absolute,
+    })
+}
+
+/// Get the link style and install mode to check `link` against.
+///
+/// Options set on the command line or in the configuration files are always used, as relinking
+/// would apply them. Otherwise paths are expected to be as they were installed, going by their
+/// store entry, rather than in the default style and mode.
+fn expected(options: &Options, store: &Store, mut link: Link) -> (Link, LinkStyle) {
+    let entry = store.get(&link.target).filter(|e| e.source == link.source);
+    let style = options
+        .chosen_link_style()
+        .or(entry.and_then(|e| e.options.link_style))
+        .unwrap_or(options.link_style);
+
+    if !options.chosen_mode()
+        && let Some(entry) = entry
+    {
+        link.mode = match entry.kind {
+            EntryKind::Link | EntryKind::Folded => InstallMode::Symlink,
+            EntryKind::Hardlink => InstallMode::Hardlink,
+            EntryKind::Copy => InstallMode::Copy,
+            EntryKind::Directory => link.mode,
+        };
+    }
+    (link, style)
+}
+
+/// Get the state of every path belonging to `package` inside `target`, sorted by target path.
+///
+/// Paths are found from the files in the package and from the store entries recorded for the
+/// package so files removed from the package are reported too. Each path is compared with the
+/// options it was installed with, see [expected].
+pub fn package_status(
+    options: &Options,
+    package: &Path,
+    target: &Path,
+    store: &Store,
+) -> Result<BTreeMap<PathBuf, State>> {
+    let mut states = BTreeMap::new();
+
+    let canonical_package = package.canonicalize()?;
+    let absolute_target = target.absolutize()?.into_owned();
+    let entries: Vec<(&PathBuf, &Entry)> = store
+        .iter()
+        .filter(|(path, entry)| entry.belongs_to(path, &canonical_package, &absolute_target))
+        .collect();
+
+    // The package's names are mapped the way they were when it was linked
+    let mut options = options.to_owned();
+    options.dotfiles |= entries.iter().any(|(_, e)| e.options.dotfiles);
+
+    for link in get_paths(&options, package, target, false)? {
+        // Paths inside folded directories are the package's own files
+        if store.in_fold(&link.target) {
+            continue;
+        }
+        let (link, style) = expected(&options, store, link);
+        let state = link_state(store, &link, style)?;
+        states.insert(link.target, state);
+    }
+
+    for (path, _) in entries {
+        if !states.contains_key(path) {
+            states.insert(path.to_owned(), State::Zombie);
+        }
+    }
+
+    Ok(states)
+}
+
+/// Print the install state of each package and return an error if any of them are out of sync.
+///
+/// Only the paths that aren't linked are listed, one per line with the state and target path
+/// separated by a tab, after a summary line for the package. When no packages are given every
+/// package the store knows about in its target directory is checked.
+pub fn status(options: &Options, store: &Store) -> Result<()> {
+    let packages: Vec<PathBuf> = match options.packages.is_empty() {
+        false => options.packages.to_owned(),
+        true => installed_packages(options, store),
+    };
+
+    let mut out_of_sync = HashSet::new();
+    for package in packages.iter() {
+        let states = match options.for_package(package).and_then(|options| {
+            let target = options.target.absolutize()?.into_owned();
+            package_status(&options, package, &target, store)
+        }) {
+            Ok(states) => states,
+            Err(e) => {
+                error!(
+                    "Failed to get the status of package {:?} due to: {}",
+                    package, e
+                );
+                out_of_sync.insert(package);
+                continue;
+            }
+        };
+
+        let mut counts: BTreeMap<State, usize> = BTreeMap::new();
+        for state in states.values() {
+            *counts.entry(*state).or_default() += 1;
+        }
+        let summary = counts
+            .iter()
+            .map(|(state, count)| format!("{} {}", count, state))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let summary = match summary.is_empty() {
+            true => "nothing to link".to_string(),
+            false => summary,
+        };
+        println!("{}: {}", package.display(), summary);
+
+        for (path, state) in states.iter() {
+            match state {
+                State::Linked => debug!("{}\t{}", state, path.display()),
+                _ => {
+                    println!("{}\t{}", state, path.display());
+                    out_of_sync.insert(package);
+                }
+            }
+        }
+    }
+
+    match out_of_sync.len() {
+        0 => Ok(()),
+        n => Err(anyhow!(
+            "{} of {} packages are out of sync",
+            n,
+            packages.len()
+        )),
+    }
+}
+
+/// Find the packages in the store that are installed in the target directory they would be linked
+/// into with the current options.
+fn installed_packages(options: &Options, store: &Store) -> Vec<PathBuf> {
+    let installed: BTreeSet<(&PathBuf, &PathBuf)> = store
+        .iter()
+        .filter_map(|(_, e)| e.package.as_ref().zip(e.target_root.as_ref()))
+        .collect();
+
+    installed
+        .iter()
+        .filter(|(package, target_root)| {
+            options
+                .for_package(package)
+                .and_then(|options| Ok(options.target.absolutize()?.into_owned()))
+                .is_ok_and(|target| target == **target_root)
+        })
+        .map(|(package, _)| package.to_path_buf())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
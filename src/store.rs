@@ -0,0 +1,876 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use anyhow::{Result, anyhow};
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
+use bincode::{Decode, Encode};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::cli::{InstallMode, LinkStyle};
+use crate::journal::{Change, Journal};
+use crate::options::Options;
+
+/// Bytes at the start of every versioned store file. Stores written before versioning was added
+/// are a bare bincode encoded `HashMap<PathBuf, PathBuf>` and never start with these bytes.
+const MAGIC: &[u8; 4] = b"LASH";
+
+/// The version of the store layout written by this version of lash.
+pub const STORE_VERSION: u32 = 4;
+
+/// What kind of file system entry lash created (or took ownership of) at the target path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    /// A directory mirroring a directory inside the package
+    Directory,
+    /// A symbolic link pointing to a file inside the package
+    Link,
+    /// A symbolic link standing in for a whole directory inside the package
+    Folded,
+    /// A hard link to a file inside the package
+    Hardlink,
+    /// A copy of a file inside the package
+    Copy,
+}
+
+impl std::fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntryKind::Directory => write!(f, "directory"),
+            EntryKind::Link => write!(f, "link"),
+            EntryKind::Folded => write!(f, "folded"),
+            EntryKind::Hardlink => write!(f, "hardlink"),
+            EntryKind::Copy => write!(f, "copy"),
+        }
+    }
+}
+
+/// The options that were in effect when an entry was created.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EntryOptions {
+    /// Whether "dot-" was mapped to "." when generating the target path
+    pub dotfiles: bool,
+    /// Whether the file at the target was adopted into the package when the entry was created
+    pub adopt: bool,
+    /// How the destination of links was written. Not known for entries migrated from version 3 of
+    /// the store or earlier.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link_style: Option<LinkStyle>,
+}
+
+/// The layout of [EntryOptions] in versions 1 to 3 of the store, before the link style was
+/// recorded.
+#[derive(Decode)]
+struct EntryOptionsV1 {
+    dotfiles: bool,
+    adopt: bool,
+}
+
+impl From<EntryOptionsV1> for EntryOptions {
+    fn from(options: EntryOptionsV1) -> Self {
+        Self {
+            dotfiles: options.dotfiles,
+            adopt: options.adopt,
+            link_style: None,
+        }
+    }
+}
+
+/// Information recorded about a single path that lash manages.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// The file or directory inside the package the entry was created from
+    #[serde(with = "os_path")]
+    pub source: PathBuf,
+    /// The package that owns the entry. Not known for entries migrated from an unversioned store.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "os_path::option"
+    )]
+    pub package: Option<PathBuf>,
+    /// The target directory the package was installed into. Not known for entries migrated from
+    /// an unversioned store.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "os_path::option"
+    )]
+    pub target_root: Option<PathBuf>,
+    /// What was created at the target path
+    pub kind: EntryKind,
+    /// The options used when the entry was created
+    #[serde(default)]
+    pub options: EntryOptions,
+    /// Seconds since the UNIX epoch when the entry was created. `0` for migrated entries.
+    #[serde(default)]
+    pub timestamp: u64,
+    /// Where the path that was in the way of the entry was moved to
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "os_path::option"
+    )]
+    pub backup: Option<PathBuf>,
+    /// Hash of the file's contents when it was installed, see [content_hash]. Only recorded for
+    /// hard links and copies. Written as hex since TOML can't hold every `u64`.
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "hex_hash")]
+    pub hash: Option<u64>,
+}
+
+/// (De)serialize an [Entry]'s hash as a hex string, also reading the plain integers written by
+/// earlier versions.
+mod hex_hash {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Hash {
+        Hex(String),
+        Int(u64),
+    }
+
+    pub fn serialize<S: Serializer>(hash: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match hash {
+            Some(hash) => serializer.serialize_str(&format!("{:016x}", hash)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u64>, D::Error> {
+        match Option::<Hash>::deserialize(deserializer)? {
+            Some(Hash::Hex(hex)) => u64::from_str_radix(&hex, 16)
+                .map(Some)
+                .map_err(|e| D::Error::custom(format!("invalid hash {:?}: {}", hex, e))),
+            Some(Hash::Int(hash)) => Ok(Some(hash)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// (De)serialize paths as strings, or as their bytes when they aren't UTF-8 since serde can't
+/// write those otherwise. Use [os_path::option] for optional paths.
+pub(crate) mod os_path {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    struct OsPath<'a>(&'a Path);
+
+    impl Serialize for OsPath<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match self.0.to_str() {
+                Some(path) => serializer.serialize_str(path),
+                None => serializer.collect_seq(self.0.as_os_str().as_bytes()),
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Str(String),
+        Bytes(Vec<u8>),
+    }
+
+    impl From<Repr> for PathBuf {
+        fn from(repr: Repr) -> Self {
+            match repr {
+                Repr::Str(path) => PathBuf::from(path),
+                Repr::Bytes(bytes) => PathBuf::from(OsStr::from_bytes(&bytes)),
+            }
+        }
+    }
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        OsPath(path).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        Repr::deserialize(deserializer).map(PathBuf::from)
+    }
+
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            path: &Option<PathBuf>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            path.as_deref().map(OsPath).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<PathBuf>, D::Error> {
+            Ok(Option::<Repr>::deserialize(deserializer)?.map(PathBuf::from))
+        }
+    }
+}
+
+/// The raw bytes of a path. bincode refuses to encode paths that aren't UTF-8, encoding the bytes
+/// instead gives the same data for every other path.
+fn path_bytes(path: &Path) -> &[u8] {
+    path.as_os_str().as_bytes()
+}
+
+fn bytes_path(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+/// Encoded field by field in declaration order like a derived implementation, with the paths as
+/// [path_bytes].
+impl Encode for Entry {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        path_bytes(&self.source).encode(encoder)?;
+        self.package.as_deref().map(path_bytes).encode(encoder)?;
+        self.target_root
+            .as_deref()
+            .map(path_bytes)
+            .encode(encoder)?;
+        self.kind.encode(encoder)?;
+        self.options.encode(encoder)?;
+        self.timestamp.encode(encoder)?;
+        self.backup.as_deref().map(path_bytes).encode(encoder)?;
+        self.hash.encode(encoder)
+    }
+}
+
+impl<Context> Decode<Context> for Entry {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        Ok(Self {
+            source: bytes_path(Decode::decode(decoder)?),
+            package: Option::<Vec<u8>>::decode(decoder)?.map(bytes_path),
+            target_root: Option::<Vec<u8>>::decode(decoder)?.map(bytes_path),
+            kind: Decode::decode(decoder)?,
+            options: Decode::decode(decoder)?,
+            timestamp: Decode::decode(decoder)?,
+            backup: Option::<Vec<u8>>::decode(decoder)?.map(bytes_path),
+            hash: Decode::decode(decoder)?,
+        })
+    }
+}
+
+/// The layout of an [Entry] in version 3 of the store, before the link style was recorded.
+struct EntryV3(Entry);
+
+impl<Context> Decode<Context> for EntryV3 {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        Ok(Self(Entry {
+            source: bytes_path(Decode::decode(decoder)?),
+            package: Option::<Vec<u8>>::decode(decoder)?.map(bytes_path),
+            target_root: Option::<Vec<u8>>::decode(decoder)?.map(bytes_path),
+            kind: Decode::decode(decoder)?,
+            options: EntryOptionsV1::decode(decoder)?.into(),
+            timestamp: Decode::decode(decoder)?,
+            backup: Option::<Vec<u8>>::decode(decoder)?.map(bytes_path),
+            hash: Decode::decode(decoder)?,
+        }))
+    }
+}
+
+/// The layout of an [Entry] in version 1 of the store, before backups were recorded.
+#[derive(Decode)]
+struct EntryV1 {
+    source: PathBuf,
+    package: Option<PathBuf>,
+    target_root: Option<PathBuf>,
+    kind: EntryKind,
+    options: EntryOptionsV1,
+    timestamp: u64,
+}
+
+impl From<EntryV1> for Entry {
+    fn from(entry: EntryV1) -> Self {
+        Self {
+            source: entry.source,
+            package: entry.package,
+            target_root: entry.target_root,
+            kind: entry.kind,
+            options: entry.options.into(),
+            timestamp: entry.timestamp,
+            backup: None,
+            hash: None,
+        }
+    }
+}
+
+/// The layout of an [Entry] in version 2 of the store, before content hashes were recorded.
+#[derive(Decode)]
+struct EntryV2 {
+    source: PathBuf,
+    package: Option<PathBuf>,
+    target_root: Option<PathBuf>,
+    kind: EntryKind,
+    options: EntryOptionsV1,
+    timestamp: u64,
+    backup: Option<PathBuf>,
+}
+
+impl From<EntryV2> for Entry {
+    fn from(entry: EntryV2) -> Self {
+        Self {
+            source: entry.source,
+            package: entry.package,
+            target_root: entry.target_root,
+            kind: entry.kind,
+            options: entry.options.into(),
+            timestamp: entry.timestamp,
+            backup: entry.backup,
+            hash: None,
+        }
+    }
+}
+
+impl Entry {
+    /// Create a new entry for a file or directory that is being installed now.
+    pub fn new(
+        source: &Path,
+        package: &Path,
+        target_root: &Path,
+        kind: EntryKind,
+        options: &Options,
+    ) -> Self {
+        Self {
+            source: source.to_owned(),
+            package: Some(package.to_owned()),
+            target_root: Some(target_root.to_owned()),
+            kind,
+            options: EntryOptions {
+                dotfiles: options.dotfiles,
+                adopt: false,
+                link_style: Some(options.link_style),
+            },
+            timestamp: now(),
+            backup: None,
+            hash: None,
+        }
+    }
+
+    /// Record the hash of the source's contents for hard links and copies so changes to them can
+    /// be noticed later.
+    pub fn with_hash(mut self) -> Result<Self> {
+        if matches!(self.kind, EntryKind::Hardlink | EntryKind::Copy) {
+            self.hash = Some(content_hash(&self.source)?);
+        }
+        Ok(self)
+    }
+
+    /// Check if the entry was created for `package`.
+    ///
+    /// Entries migrated from an unversioned store do not know their package so fall back to
+    /// checking if the source is inside the package.
+    pub fn owned_by(&self, package: &Path) -> bool {
+        match &self.package {
+            Some(p) => p == package,
+            None => self.source.starts_with(package),
+        }
+    }
+
+    /// Check if the entry was created for `package` inside `target_root`.
+    ///
+    /// Entries migrated from an unversioned store do not know their package so fall back to
+    /// comparing the path prefixes of the source and target.
+    pub fn belongs_to(&self, target: &Path, package: &Path, target_root: &Path) -> bool {
+        match (&self.package, &self.target_root) {
+            (Some(p), Some(t)) => p == package && t == target_root,
+            _ => target.starts_with(target_root) && self.source.starts_with(package),
+        }
+    }
+
+    /// Check if the hard link or copy at `target` has been changed since it was installed.
+    ///
+    /// A hard link that still shares its inode with the source is never modified, edits made
+    /// through it change the package file too. Otherwise the contents are compared with the hash
+    /// recorded when the entry was created. Always `false` for other kinds of entry.
+    pub fn is_modified(&self, target: &Path) -> Result<bool> {
+        match self.kind {
+            EntryKind::Hardlink if same_file(target, &self.source) => Ok(false),
+            EntryKind::Hardlink | EntryKind::Copy => Ok(self.hash != Some(content_hash(target)?)),
+            _ => Ok(false),
+        }
+    }
+
+    /// Check if the unmodified hard link or copy at `target` is what installing the source in
+    /// `mode` would create now, so it doesn't need to be replaced.
+    pub fn is_current(&self, target: &Path, mode: InstallMode) -> Result<bool> {
+        match (self.kind, mode) {
+            (EntryKind::Hardlink, InstallMode::Hardlink) => Ok(same_file(target, &self.source)),
+            (EntryKind::Copy, InstallMode::Copy) => {
+                Ok(self.hash == Some(content_hash(&self.source)?))
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+/// The layout used when exporting the store to a readable format.
+///
+/// Entries are sorted by target path so the output is stable. They are listed with their targets
+/// rather than keyed by them so targets that aren't UTF-8 can be written with [os_path].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedStore {
+    pub version: u32,
+    #[serde(default, deserialize_with = "exported_entries")]
+    pub entries: Vec<ExportedEntry>,
+}
+
+/// A store [Entry] together with the target it is recorded for.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedEntry {
+    #[serde(with = "os_path")]
+    pub target: PathBuf,
+    #[serde(flatten)]
+    pub entry: Entry,
+}
+
+/// Read the entries of an [ExportedStore], also accepting the map from target to entry written by
+/// earlier versions.
+fn exported_entries<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<ExportedEntry>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Entries {
+        List(Vec<ExportedEntry>),
+        Map(BTreeMap<PathBuf, Entry>),
+    }
+
+    Ok(match serde::Deserialize::deserialize(deserializer)? {
+        Entries::List(entries) => entries,
+        Entries::Map(entries) => entries
+            .into_iter()
+            .map(|(target, entry)| ExportedEntry { target, entry })
+            .collect(),
+    })
+}
+
+/// The on-disk location of an opened [Store] and the lock held on it.
+#[derive(Debug)]
+struct StoreFile {
+    /// Where the store is persisted
+    path: PathBuf,
+    /// Held for as long as the store is open. The advisory lock is released when it is dropped.
+    _lock: File,
+}
+
+/// The record of every path lash has created or taken ownership of. Keyed by the target path.
+///
+/// A store opened with [Store::open] holds an exclusive lock until it is dropped and writes any
+/// outstanding changes back to disk when it is flushed or dropped (including while unwinding
+/// from a panic).
+#[derive(Debug)]
+pub struct Store {
+    /// The layout version of the store
+    pub version: u32,
+    /// The managed paths
+    pub entries: HashMap<PathBuf, Entry>,
+    /// Set when the store was opened from disk rather than created in memory
+    file: Option<StoreFile>,
+    /// Whether there are changes that haven't been written to disk yet
+    dirty: bool,
+    /// Record of the changes made to the store and the file system
+    pub journal: Journal,
+}
+
+impl Store {
+    /// Create an empty store using the current layout version.
+    pub fn new() -> Self {
+        Self::from_entries(HashMap::new())
+    }
+
+    /// Lock and load the store at `path`, creating an empty store if it does not exist yet.
+    ///
+    /// If another lash process holds the lock this waits until it is released.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut store = Self::lock(path)?;
+        store.reload()?;
+        Ok(store)
+    }
+
+    /// Lock and load the store at `path` like [Store::open], but if the store can't be read start
+    /// from an empty store instead of failing. The unreadable store is kept next to `path` with a
+    /// `.corrupt` extension. With `dry_run` the store on disk is left as it is.
+    pub fn open_or_empty(path: &Path, dry_run: bool) -> Result<Self> {
+        let mut store = Self::lock(path)?;
+        if let Err(e) = store.reload() {
+            let corrupt = path.with_extension("corrupt");
+            warn!(
+                "Could not read the store ({}), starting from an empty store. The old store {} \
+                 moved to {:?}",
+                e,
+                match dry_run {
+                    true => "would be",
+                    false => "has been",
+                },
+                corrupt
+            );
+            if !dry_run {
+                fs::rename(path, &corrupt)?;
+                store.dirty = true;
+            }
+        }
+        Ok(store)
+    }
+
+    /// Lock the store at `path` without reading it. The store starts out empty.
+    ///
+    /// If another lash process holds the lock this waits until it is released.
+    pub fn lock(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let lock_path = path.with_extension("lock");
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)?;
+
+        match lock.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                info!(
+                    "Waiting for another lash process to release {:?}",
+                    lock_path
+                );
+                lock.lock()?;
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        debug!("Acquired store lock {:?}", lock_path);
+
+        let mut store = Self::new();
+        store.file = Some(StoreFile {
+            path: path.to_owned(),
+            _lock: lock,
+        });
+        store.journal = Journal::open(&path.with_file_name("journal"))?;
+
+        Ok(store)
+    }
+
+    /// Replace the contents of the store with the contents of the file it was opened from.
+    fn reload(&mut self) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+
+        if !file.path.exists() {
+            debug!("Store does not exist, continuing");
+            return Ok(());
+        }
+
+        let mut loaded = Self::load(&file.path)?;
+        self.entries = std::mem::take(&mut loaded.entries);
+        self.dirty = loaded.dirty;
+        Ok(())
+    }
+
+    /// Read the store from `path`.
+    ///
+    /// Stores written in an older layout are migrated to the current layout in memory. They are
+    /// written out in the current layout the next time the store is saved.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut data = Vec::new();
+        fs::File::open(path)?.read_to_end(&mut data)?;
+
+        let config = bincode::config::standard();
+
+        let Some(versioned) = data.strip_prefix(MAGIC) else {
+            info!("Migrating store from the unversioned layout");
+            let (legacy, _): (HashMap<PathBuf, PathBuf>, usize) =
+                bincode::decode_from_slice(&data, config)?;
+            return Ok(Self::from_legacy(legacy));
+        };
+
+        let (version, read): (u32, usize) = bincode::decode_from_slice(versioned, config)?;
+        match version {
+            1 => {
+                info!("Migrating store from version 1");
+                let (entries, _): (HashMap<PathBuf, EntryV1>, usize) =
+                    bincode::decode_from_slice(&versioned[read..], config)?;
+                let mut store =
+                    Self::from_entries(entries.into_iter().map(|(t, e)| (t, e.into())).collect());
+                store.dirty = true;
+                Ok(store)
+            }
+            2 => {
+                info!("Migrating store from version 2");
+                let (entries, _): (HashMap<PathBuf, EntryV2>, usize) =
+                    bincode::decode_from_slice(&versioned[read..], config)?;
+                let mut store =
+                    Self::from_entries(entries.into_iter().map(|(t, e)| (t, e.into())).collect());
+                store.dirty = true;
+                Ok(store)
+            }
+            3 => {
+                info!("Migrating store from version 3");
+                let (entries, _): (Vec<(Vec<u8>, EntryV3)>, usize) =
+                    bincode::decode_from_slice(&versioned[read..], config)?;
+                let mut store = Self::from_entries(
+                    entries
+                        .into_iter()
+                        .map(|(t, e)| (bytes_path(t), e.0))
+                        .collect(),
+                );
+                store.dirty = true;
+                Ok(store)
+            }
+            STORE_VERSION => {
+                // Encoded like a map from target to entry, see [Store::save]
+                let (entries, _): (Vec<(Vec<u8>, Entry)>, usize) =
+                    bincode::decode_from_slice(&versioned[read..], config)?;
+                Ok(Self::from_entries(
+                    entries
+                        .into_iter()
+                        .map(|(t, e)| (bytes_path(t), e))
+                        .collect(),
+                ))
+            }
+            v => Err(anyhow!(
+                "Store version {} is newer than the supported version {}",
+                v,
+                STORE_VERSION
+            )),
+        }
+    }
+
+    /// Write the store to `path` in the current layout.
+    ///
+    /// The data is written to a temporary file next to `path` which is then renamed over `path`
+    /// so the store on disk is always either the old or the new version, never a partial write.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let config = bincode::config::standard();
+        let mut data = MAGIC.to_vec();
+        data.extend(bincode::encode_to_vec(STORE_VERSION, config)?);
+        // A list of pairs is encoded the same as a map, with the targets as raw bytes
+        let entries: Vec<(&[u8], &Entry)> = self
+            .entries
+            .iter()
+            .map(|(t, e)| (path_bytes(t), e))
+            .collect();
+        data.extend(bincode::encode_to_vec(entries, config)?);
+
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(&data)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, path)?;
+
+        // Make sure the rename itself survives a crash
+        if let Some(parent) = path.parent()
+            && let Ok(dir) = File::open(parent)
+        {
+            let _ = dir.sync_all();
+        }
+
+        Ok(())
+    }
+
+    /// Write any outstanding changes to the file the store was opened from, after syncing the
+    /// journal so the changes it records are on disk first.
+    ///
+    /// Does nothing for stores that were not opened with [Store::open].
+    pub fn flush(&mut self) -> Result<()> {
+        self.journal.sync()?;
+        if !self.dirty {
+            return Ok(());
+        }
+        if let Some(file) = &self.file {
+            debug!("Flushing store to {:?}", file.path);
+            self.save(&file.path)?;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Convert the target -> source map used before the store was versioned.
+    fn from_legacy(legacy: HashMap<PathBuf, PathBuf>) -> Self {
+        let entries = legacy
+            .into_iter()
+            .map(|(target, source)| {
+                let kind = match source.is_dir() {
+                    true => EntryKind::Directory,
+                    false => EntryKind::Link,
+                };
+                let entry = Entry {
+                    source,
+                    package: None,
+                    target_root: None,
+                    kind,
+                    options: EntryOptions::default(),
+                    timestamp: 0,
+                    backup: None,
+                    hash: None,
+                };
+                (target, entry)
+            })
+            .collect();
+
+        let mut store = Self::from_entries(entries);
+        // Make sure the migrated layout is written out
+        store.dirty = true;
+        store
+    }
+
+    fn from_entries(entries: HashMap<PathBuf, Entry>) -> Self {
+        Self {
+            version: STORE_VERSION,
+            entries,
+            file: None,
+            dirty: false,
+            journal: Journal::default(),
+        }
+    }
+
+    /// Convert the store into the layout used for exporting.
+    pub fn export(&self) -> ExportedStore {
+        let mut entries: Vec<ExportedEntry> = self
+            .entries
+            .iter()
+            .map(|(target, entry)| ExportedEntry {
+                target: target.to_owned(),
+                entry: entry.to_owned(),
+            })
+            .collect();
+        entries.sort_by(|a, b| a.target.cmp(&b.target));
+        ExportedStore {
+            version: STORE_VERSION,
+            entries,
+        }
+    }
+
+    pub fn get(&self, target: &Path) -> Option<&Entry> {
+        self.entries.get(target)
+    }
+
+    pub fn contains_key(&self, target: &Path) -> bool {
+        self.entries.contains_key(target)
+    }
+
+    /// Check if `path` is inside a folded directory, so it is really a path inside a package.
+    pub fn in_fold(&self, path: &Path) -> bool {
+        path.ancestors().skip(1).any(|dir| {
+            dir.is_symlink()
+                && self
+                    .entries
+                    .get(dir)
+                    .is_some_and(|e| e.kind == EntryKind::Folded)
+        })
+    }
+
+    pub fn insert(&mut self, target: PathBuf, entry: Entry) -> Option<Entry> {
+        self.dirty = true;
+        let previous = self.entries.insert(target.to_owned(), entry.to_owned());
+        self.record(target, previous.to_owned(), Some(entry));
+        previous
+    }
+
+    pub fn remove(&mut self, target: &Path) -> Option<Entry> {
+        let entry = self.entries.remove(target);
+        if entry.is_some() {
+            self.dirty = true;
+            self.record(target.to_owned(), entry.to_owned(), None);
+        }
+        entry
+    }
+
+    pub fn clear(&mut self) {
+        let targets: Vec<PathBuf> = self.entries.keys().cloned().collect();
+        for target in targets {
+            self.remove(&target);
+        }
+    }
+
+    /// Record a change to an entry in the journal.
+    ///
+    /// The entry has already been changed so failing to record it is only reported.
+    fn record(&mut self, target: PathBuf, previous: Option<Entry>, current: Option<Entry>) {
+        let change = Change::SetEntry {
+            target,
+            previous: previous.map(Box::new),
+            current: current.map(Box::new),
+        };
+        if let Err(e) = self.journal.record(change) {
+            error!("Failed to record store change in the journal: {:?}", e);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&PathBuf, &Entry)> {
+        self.entries.iter()
+    }
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Store {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("Failed to write data to the store: {:?}", e);
+        }
+    }
+}
+
+/// Seconds since the UNIX epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Check if `a` and `b` are hard links to the same file.
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::symlink_metadata(a), fs::symlink_metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Hash the contents of the file at `path` with 64 bit FNV-1a.
+///
+/// This is only used to notice when an installed copy has changed, not for security, so a fast
+/// non-cryptographic hash is enough. It is stored so must not change between versions.
+pub fn content_hash(path: &Path) -> Result<u64> {
+    const OFFSET: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    let mut file = File::open(path)?;
+    let mut buffer = [0; 8192];
+    let mut hash = OFFSET;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        for byte in &buffer[..read] {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(PRIME);
+        }
+    }
+    Ok(hash)
+}
@@ -0,0 +1,267 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use anyhow::{Result, anyhow};
+use path_absolutize::Absolutize;
+
+use crate::cli::{Format, LinkStyle, StoreCommand};
+use crate::command::rebuild_package;
+use crate::options::Options;
+use crate::store::{Entry, ExportedStore, STORE_VERSION, Store};
+
+/// Performs one of the `lash store` commands against the store at `store_path`.
+///
+/// The store is only opened (and locked) by the commands that need to read or change it so the
+/// location of the store can still be printed if it can't be read.
+pub fn process_store_command(
+    options: &Options,
+    command: &StoreCommand,
+    store_path: &Path,
+) -> Result<()> {
+    if let StoreCommand::Path = command {
+        println!("{}", store_path.display());
+        return Ok(());
+    }
+
+    let mut store = match command {
+        StoreCommand::Rebuild { .. } => Store::open_or_empty(store_path, options.dry_run)?,
+        _ => Store::open(store_path)?,
+    };
+
+    match command {
+        StoreCommand::List { package, prefix } => {
+            list(&store, package.as_deref(), prefix.as_deref())
+        }
+        StoreCommand::Show { path } => show(&store, path),
+        StoreCommand::Forget { paths } => forget(options, &mut store, paths),
+        StoreCommand::Export { format, output } => export(&store, *format, output.as_deref()),
+        StoreCommand::Import {
+            format,
+            replace,
+            input,
+        } => import(options, &mut store, *format, *replace, input.as_deref()),
+        StoreCommand::Rebuild { packages } => rebuild(options, &mut store, packages),
+        // Handled before opening the store
+        StoreCommand::Path => Ok(()),
+    }?;
+
+    store.flush()
+}
+
+/// Get the path a package is recorded under in the store.
+///
+/// Packages are recorded by their canonical path but a package that has since been deleted can't
+/// be canonicalized so fall back to the absolute path.
+fn package_path(package: &Path) -> Result<PathBuf> {
+    match package.canonicalize() {
+        Ok(p) => Ok(p),
+        Err(_) => Ok(package.absolutize()?.into_owned()),
+    }
+}
+
+/// Print the entries in the store sorted by target path, one per line.
+///
+/// Each line contains the kind of entry, the target path and the source path separated by tabs.
+fn list(store: &Store, package: Option<&Path>, prefix: Option<&Path>) -> Result<()> {
+    let package = package.map(package_path).transpose()?;
+    let prefix = prefix
+        .map(|p| p.absolutize().map(|p| p.into_owned()))
+        .transpose()?;
+
+    let mut entries: Vec<(&PathBuf, &Entry)> = store
+        .iter()
+        .filter(|(_, e)| package.as_ref().is_none_or(|p| e.owned_by(p)))
+        .filter(|(t, _)| prefix.as_ref().is_none_or(|p| t.starts_with(p)))
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+
+    for (target, entry) in entries {
+        println!(
+            "{}\t{}\t{}",
+            entry.kind,
+            target.display(),
+            entry.source.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Print everything recorded about a single target path.
+fn show(store: &Store, path: &Path) -> Result<()> {
+    let target = path.absolutize()?;
+    let entry = store
+        .get(&target)
+        .ok_or(anyhow!("{:?} is not in the store", target))?;
+
+    let unknown = || "unknown".to_string();
+
+    println!("target:      {}", target.display());
+    println!("source:      {}", entry.source.display());
+    println!(
+        "package:     {}",
+        entry
+            .package
+            .as_ref()
+            .map_or_else(unknown, |p| p.display().to_string())
+    );
+    println!(
+        "target root: {}",
+        entry
+            .target_root
+            .as_ref()
+            .map_or_else(unknown, |p| p.display().to_string())
+    );
+    println!("kind:        {}", entry.kind);
+    println!("dotfiles:    {}", entry.options.dotfiles);
+    println!("adopt:       {}", entry.options.adopt);
+    println!(
+        "link style:  {}",
+        match entry.options.link_style {
+            Some(LinkStyle::Absolute) => "absolute".to_string(),
+            Some(LinkStyle::Relative) => "relative".to_string(),
+            None => unknown(),
+        }
+    );
+    if let Some(backup) = &entry.backup {
+        println!("backup:      {}", backup.display());
+    }
+    println!(
+        "created:     {}",
+        match entry.timestamp {
+            0 => unknown(),
+            t => humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(t)).to_string(),
+        }
+    );
+
+    Ok(())
+}
+
+/// Remove target paths, or every entry belonging to a package, from the store.
+///
+/// Nothing is removed unless every path matches at least one entry.
+fn forget(options: &Options, store: &mut Store, paths: &[PathBuf]) -> Result<()> {
+    for key in resolve_paths(store, paths)? {
+        info!("Forgetting {:?}", key);
+        if !options.dry_run {
+            store.remove(&key);
+        }
+    }
+
+    Ok(())
+}
+
+/// Find the store entries for each of `paths`, which can either be target paths in the store or
+/// packages. Fails if any of the paths isn't in the store so nothing is changed by mistake.
+pub fn resolve_paths(store: &Store, paths: &[PathBuf]) -> Result<HashSet<PathBuf>> {
+    let mut keys: HashSet<PathBuf> = HashSet::new();
+
+    for path in paths {
+        let target = path.absolutize()?;
+        if store.contains_key(&target) {
+            keys.insert(target.into_owned());
+            continue;
+        }
+
+        let package = package_path(path)?;
+        let owned: Vec<PathBuf> = store
+            .iter()
+            .filter(|(_, e)| e.owned_by(&package))
+            .map(|(t, _)| t.to_owned())
+            .collect();
+
+        if owned.is_empty() {
+            return Err(anyhow!("{:?} is not a path or package in the store", path));
+        }
+        keys.extend(owned);
+    }
+
+    Ok(keys)
+}
+
+/// Work out the format to use from the command line or the extension of the file.
+fn resolve_format(format: Option<Format>, file: Option<&Path>) -> Format {
+    format.unwrap_or_else(
+        || match file.and_then(|f| f.extension()).and_then(|e| e.to_str()) {
+            Some("toml") => Format::Toml,
+            _ => Format::Json,
+        },
+    )
+}
+
+/// Write the store to `output` (or stdout) in a readable format.
+fn export(store: &Store, format: Option<Format>, output: Option<&Path>) -> Result<()> {
+    let exported = store.export();
+
+    let data = match resolve_format(format, output) {
+        Format::Json => serde_json::to_string_pretty(&exported)? + "\n",
+        Format::Toml => toml::to_string_pretty(&exported)?,
+    };
+
+    match output {
+        Some(path) => fs::write(path, data)?,
+        None => std::io::stdout().write_all(data.as_bytes())?,
+    }
+
+    Ok(())
+}
+
+/// Read entries written by [export] from `input` (or stdin) into the store.
+fn import(
+    options: &Options,
+    store: &mut Store,
+    format: Option<Format>,
+    replace: bool,
+    input: Option<&Path>,
+) -> Result<()> {
+    let data = match input {
+        Some(path) => fs::read_to_string(path)?,
+        None => {
+            let mut data = String::new();
+            std::io::stdin().read_to_string(&mut data)?;
+            data
+        }
+    };
+
+    let imported: ExportedStore = match resolve_format(format, input) {
+        Format::Json => serde_json::from_str(&data)?,
+        Format::Toml => toml::from_str(&data)?,
+    };
+
+    if imported.version > STORE_VERSION {
+        return Err(anyhow!(
+            "Imported store version {} is newer than the supported version {}",
+            imported.version,
+            STORE_VERSION
+        ));
+    }
+
+    info!("Importing {} entries", imported.entries.len());
+    if options.dry_run {
+        return Ok(());
+    }
+
+    if replace {
+        store.clear();
+    }
+    for exported in imported.entries {
+        store.insert(exported.target, exported.entry);
+    }
+
+    Ok(())
+}
+
+/// Replace the store entries for each package with the entries found by scanning the target.
+fn rebuild(options: &Options, store: &mut Store, packages: &[PathBuf]) -> Result<()> {
+    for package in packages {
+        info!("Rebuilding store entries for package {:?}", package);
+        rebuild_package(options, package, store)?;
+    }
+    Ok(())
+}
@@ -7,6 +7,7 @@ use assert_fs::prelude::*;
 fn link_1_file_no_overwrite() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
     let in_file = package.child("file.txt");
     let out_file = output.child("file.txt");
     let out_contents: Vec<u8> = rand::random_iter().take(256).collect();
@@ -17,6 +18,7 @@ fn link_1_file_no_overwrite() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--verbose",
             "--target",
@@ -25,7 +27,8 @@ fn link_1_file_no_overwrite() {
             package.to_str().unwrap(),
         ])
         .assert()
-        .success();
+        .failure()
+        .stderr(predicates::str::contains("is a file"));
 
     let contents: Vec<u8> = fs::read(in_file.path()).unwrap();
 
@@ -37,12 +40,14 @@ fn link_1_file_no_overwrite() {
 
     package.close().unwrap();
     output.close().unwrap();
+    store.close().unwrap();
 }
 
 #[test]
 fn link_1_file() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
     let in_file = package.child("file.txt");
     let out_file = output.child("file.txt");
 
@@ -51,6 +56,7 @@ fn link_1_file() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),
@@ -66,6 +72,7 @@ fn link_1_file() {
 
     package.close().unwrap();
     output.close().unwrap();
+    store.close().unwrap();
 }
 
 #[test]
@@ -77,6 +84,7 @@ fn link_1_file() {
 fn link_1_file_nested_symlinks() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
     let in_file = package.child("file.txt");
     let out_file = output.child("file.txt");
     let mid_file = output.child("mid");
@@ -88,6 +96,7 @@ fn link_1_file_nested_symlinks() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),
@@ -115,6 +124,7 @@ fn link_1_file_nested_symlinks() {
 
     package.close().unwrap();
     output.close().unwrap();
+    store.close().unwrap();
 }
 
 #[test]
@@ -122,6 +132,7 @@ fn link_1_file_nested_symlinks() {
 fn link_1_file_relative_symlinks() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
     let in_file = package.child("file.txt");
     let out_file = output.child("file.txt");
 
@@ -151,6 +162,7 @@ fn link_1_file_relative_symlinks() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),
@@ -182,12 +194,14 @@ fn link_1_file_relative_symlinks() {
 
     package.close().unwrap();
     output.close().unwrap();
+    store.close().unwrap();
 }
 
 #[test]
 fn unlink_1_file() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
     let in_file = package.child("file.txt");
     let out_file = output.child("file.txt");
 
@@ -197,6 +211,7 @@ fn unlink_1_file() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),
@@ -211,12 +226,14 @@ fn unlink_1_file() {
 
     package.close().unwrap();
     output.close().unwrap();
+    store.close().unwrap();
 }
 
 #[test]
 fn link_1_file_dry_run() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
     let in_file = package.child("file.txt");
     let out_file = output.child("file.txt");
 
@@ -225,6 +242,7 @@ fn link_1_file_dry_run() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--dry-run",
             "--target",
@@ -240,12 +258,14 @@ fn link_1_file_dry_run() {
 
     package.close().unwrap();
     output.close().unwrap();
+    store.close().unwrap();
 }
 
 #[test]
 fn link_1_file_adopt() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
     let in_file = package.child("file.txt");
     let out_file = output.child("file.txt");
     let out_contents: Vec<u8> = rand::random_iter().take(256).collect();
@@ -256,6 +276,7 @@ fn link_1_file_adopt() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--verbose",
             "--target",
@@ -285,12 +306,14 @@ fn link_1_file_adopt() {
 
     package.close().unwrap();
     output.close().unwrap();
+    store.close().unwrap();
 }
 
 #[test]
 fn link_1_file_adopt_dry_run() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
     let in_file = package.child("file.txt");
     let out_file = output.child("file.txt");
     let out_contents: Vec<u8> = rand::random_iter().take(256).collect();
@@ -301,6 +324,7 @@ fn link_1_file_adopt_dry_run() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--verbose",
             "--dry-run",
@@ -323,12 +347,14 @@ fn link_1_file_adopt_dry_run() {
 
     package.close().unwrap();
     output.close().unwrap();
+    store.close().unwrap();
 }
 
 #[test]
 fn unlink_1_file_dry_run() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
     let in_file = package.child("file.txt");
     let out_file = output.child("file.txt");
 
@@ -338,6 +364,7 @@ fn unlink_1_file_dry_run() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--dry-run",
             "--target",
@@ -354,4 +381,5 @@ fn unlink_1_file_dry_run() {
 
     package.close().unwrap();
     output.close().unwrap();
+    store.close().unwrap();
 }
@@ -0,0 +1,74 @@
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+
+#[test]
+fn add_files_and_directories() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let out_file = output.child(".config/foo/bar.toml");
+    let out_dir = output.child("scripts");
+    let out_script = out_dir.child("run.sh");
+    let in_package = package.child("foo");
+
+    out_file.write_str("bar = 1\n").unwrap();
+    out_script.write_str("#!/bin/sh\n").unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(package.path())
+            .env("LASH_STORE", store.path())
+            .args(["--dotfiles", "--target", output.to_str().unwrap()])
+            .args(args);
+        cmd
+    };
+
+    lash(&[
+        "add",
+        "--package",
+        in_package.to_str().unwrap(),
+        out_file.to_str().unwrap(),
+        out_dir.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    // The dotfiles mapping is reversed in the package
+    let in_file = in_package.child("dot-config/foo/bar.toml");
+    assert_eq!(std::fs::read_to_string(&in_file).unwrap(), "bar = 1\n");
+    assert_eq!(out_file.read_link().unwrap(), in_file.path());
+
+    // Directories are added with their contents, the target directory is kept
+    let in_script = in_package.child("scripts/run.sh");
+    assert_eq!(std::fs::read_to_string(&in_script).unwrap(), "#!/bin/sh\n");
+    assert!(!out_dir.is_symlink(), "Directory was replaced by a link");
+    assert_eq!(out_script.read_link().unwrap(), in_script.path());
+
+    // Everything added is recorded so the package is fully linked
+    lash(&["status", in_package.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(format!("{}: 5 linked\n", in_package.path().display()));
+
+    // Paths already in the package or outside the target directory can't be added
+    lash(&[
+        "add",
+        "--package",
+        in_package.to_str().unwrap(),
+        out_file.to_str().unwrap(),
+    ])
+    .assert()
+    .failure();
+    lash(&[
+        "add",
+        "--package",
+        in_package.to_str().unwrap(),
+        store.to_str().unwrap(),
+    ])
+    .assert()
+    .failure();
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
@@ -6,18 +6,21 @@ use std::fs;
 fn adopt_link_target_not_link() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
     let in_file = package.child("file.txt");
     let out_file = output.child("file.txt");
     let other_file = output.child("other.txt");
     let original_contents: Vec<u8> = rand::random_iter().take(256).collect();
 
     in_file.touch().unwrap();
+    package.child("plain.txt").touch().unwrap();
     other_file.write_binary(&original_contents).unwrap();
     out_file.symlink_to_file(other_file.path()).unwrap();
 
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--verbose",
             "--target",
@@ -57,8 +60,22 @@ fn adopt_link_target_not_link() {
         "Other file has still got original contents"
     );
 
+    // Only the entry for the adopted file records the adoption
+    let show = |path: &std::path::Path| {
+        let output = Command::cargo_bin(env!("CARGO_PKG_NAME"))
+            .unwrap()
+            .env("LASH_STORE", store.path())
+            .args(["store", "show", path.to_str().unwrap()])
+            .assert()
+            .success();
+        String::from_utf8(output.get_output().stdout.clone()).unwrap()
+    };
+    assert!(show(out_file.path()).contains("adopt:       true"));
+    assert!(show(output.child("plain.txt").path()).contains("adopt:       false"));
+
     package.close().unwrap();
     output.close().unwrap();
+    store.close().unwrap();
 }
 
 /// Calling [`std::fs::copy`] when src and dest are the same file seems to truncate the output.
@@ -67,6 +84,7 @@ fn adopt_link_target_not_link() {
 fn dont_copy_to_from_same_file() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
     let in_file = package.child("file.txt");
     let out_file = output.child("file.txt");
     let original_contents: Vec<u8> = rand::random_iter().take(256).collect();
@@ -92,6 +110,7 @@ fn dont_copy_to_from_same_file() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--verbose",
             "--target",
@@ -121,4 +140,206 @@ fn dont_copy_to_from_same_file() {
 
     package.close().unwrap();
     output.close().unwrap();
+    store.close().unwrap();
+}
+
+#[test]
+fn adopt_no_confirm() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let in_file = package.child("file.txt");
+    let out_file = output.child("file.txt");
+
+    in_file.write_str("package\n").unwrap();
+    out_file.write_str("target\n").unwrap();
+
+    // Nothing is asked without a terminal, --no-confirm makes that explicit for scripts
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .current_dir(package.path())
+        .env("LASH_STORE", store.path())
+        .args([
+            "--no-confirm",
+            "--target",
+            output.to_str().unwrap(),
+            "link",
+            "--adopt",
+            package.to_str().unwrap(),
+        ])
+        .write_stdin("n\n")
+        .assert()
+        .success();
+
+    assert!(out_file.is_symlink(), "Out file wasn't linked");
+    assert_eq!(fs::read_to_string(in_file.path()).unwrap(), "target\n");
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
+
+#[test]
+fn adopt_directory_with_metadata() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let in_path = package.child("config");
+    let out_dir = output.child("config");
+    let out_file = out_dir.child("nested/script.sh");
+    let out_broken = out_dir.child("broken");
+    let mtime = filetime::FileTime::from_unix_time(1_000_000_000, 0);
+
+    in_path.write_str("package file\n").unwrap();
+    out_file.write_str("#!/bin/sh\n").unwrap();
+    fs::set_permissions(&out_file, fs::Permissions::from_mode(0o750)).unwrap();
+    filetime::set_file_mtime(&out_file, mtime).unwrap();
+    out_broken.symlink_to_file("missing").unwrap();
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .current_dir(package.path())
+        .env("LASH_STORE", store.path())
+        .args([
+            "--target",
+            output.to_str().unwrap(),
+            "link",
+            "--adopt",
+            package.to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+
+    // The directory is copied into the package in place of the file
+    let in_file = in_path.child("nested/script.sh");
+    assert!(in_path.is_dir(), "Directory wasn't adopted");
+    assert_eq!(fs::read_to_string(&in_file).unwrap(), "#!/bin/sh\n");
+    let metadata = fs::metadata(&in_file).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o750);
+    assert_eq!(
+        filetime::FileTime::from_last_modification_time(&metadata),
+        mtime
+    );
+
+    // Broken links are kept as links
+    let in_broken = in_path.child("broken");
+    assert!(in_broken.is_symlink(), "Broken link wasn't kept as a link");
+    assert_eq!(
+        in_broken.read_link().unwrap(),
+        out_dir.child("missing").path()
+    );
+
+    // The target directory is kept and its files are linked to the package
+    assert!(out_dir.is_dir() && !out_dir.is_symlink());
+    assert_eq!(out_file.read_link().unwrap(), in_file.path());
+    assert_eq!(out_broken.read_link().unwrap(), in_broken.path());
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
+
+#[test]
+fn adopt_special_file_fails() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let in_file = package.child("fifo");
+    let out_file = output.child("fifo");
+
+    in_file.write_str("package\n").unwrap();
+    assert!(
+        std::process::Command::new("mkfifo")
+            .arg(out_file.path())
+            .status()
+            .unwrap()
+            .success()
+    );
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .current_dir(package.path())
+        .env("LASH_STORE", store.path())
+        .args([
+            "--target",
+            output.to_str().unwrap(),
+            "link",
+            "--adopt",
+            package.to_str().unwrap(),
+        ])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("FIFOs"));
+
+    assert_eq!(fs::read_to_string(&in_file).unwrap(), "package\n");
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
+
+#[test]
+fn adopt_strategies() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let old = filetime::FileTime::from_unix_time(1_000_000_000, 0);
+    let new = filetime::FileTime::from_unix_time(1_500_000_000, 0);
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(package.path())
+            .env("LASH_STORE", store.path())
+            .args(["--target", output.to_str().unwrap(), "link"])
+            .args(args)
+            .arg(package.path());
+        cmd
+    };
+
+    // Keep the package version, the target is backed up
+    package.child("kept.txt").write_str("package\n").unwrap();
+    output.child("kept.txt").write_str("target\n").unwrap();
+    lash(&["--adopt=package"]).assert().success();
+    assert_eq!(
+        fs::read_to_string(output.child("kept.txt")).unwrap(),
+        "package\n"
+    );
+    assert!(output.child("kept.txt").is_symlink());
+    assert!(store.child("backups").exists(), "Target wasn't backed up");
+
+    // Keep whichever version is newer
+    for (name, package_time, target_time) in [("a.txt", old, new), ("b.txt", new, old)] {
+        let in_file = package.child(name);
+        let out_file = output.child(name);
+        in_file.write_str("package\n").unwrap();
+        out_file.write_str("target\n").unwrap();
+        filetime::set_file_mtime(&in_file, package_time).unwrap();
+        filetime::set_file_mtime(&out_file, target_time).unwrap();
+    }
+    lash(&["--adopt=newer"]).assert().success();
+    assert_eq!(
+        fs::read_to_string(package.child("a.txt")).unwrap(),
+        "target\n"
+    );
+    assert_eq!(
+        fs::read_to_string(package.child("b.txt")).unwrap(),
+        "package\n"
+    );
+    assert!(output.child("a.txt").is_symlink());
+    assert!(output.child("b.txt").is_symlink());
+
+    // Asking keeps the newer version when there is nobody to ask
+    let in_file = package.child("c.txt");
+    let out_file = output.child("c.txt");
+    in_file.write_str("package\n").unwrap();
+    out_file.write_str("target\n").unwrap();
+    filetime::set_file_mtime(&in_file, old).unwrap();
+    lash(&["--adopt=ask"]).assert().success();
+    assert_eq!(fs::read_to_string(&in_file).unwrap(), "target\n");
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
 }
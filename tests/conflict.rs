@@ -0,0 +1,253 @@
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::str::contains;
+
+/// Create a package with two files and a target directory where one of them is already taken.
+fn setup() -> (assert_fs::TempDir, assert_fs::TempDir, assert_fs::TempDir) {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+
+    package.child("a.txt").write_str("package").unwrap();
+    package.child("b.txt").touch().unwrap();
+    output.child("a.txt").write_str("existing").unwrap();
+
+    (package, output, store)
+}
+
+fn lash(
+    cwd: &std::path::Path,
+    output: &assert_fs::TempDir,
+    store: &assert_fs::TempDir,
+    args: &[&str],
+) -> Command {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    cmd.current_dir(cwd)
+        .env("LASH_STORE", store.path())
+        .args(["--target", output.to_str().unwrap()])
+        .args(args);
+    cmd
+}
+
+#[test]
+fn conflict_fail() {
+    let (package, output, store) = setup();
+
+    lash(
+        &package,
+        &output,
+        &store,
+        &["link", package.to_str().unwrap()],
+    )
+    .assert()
+    .failure()
+    .stderr(contains("is a file"));
+
+    assert!(!output.child("b.txt").exists(), "Package was partly linked");
+    assert_eq!(
+        std::fs::read_to_string(output.child("a.txt")).unwrap(),
+        "existing"
+    );
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
+
+#[test]
+fn conflict_skip() {
+    let (package, output, store) = setup();
+    let cwd = assert_fs::TempDir::new().unwrap();
+
+    // The policy can be set in the configuration file
+    cwd.child("lash.toml")
+        .write_str("on_conflict = \"skip\"\n")
+        .unwrap();
+
+    lash(&cwd, &output, &store, &["link", package.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(contains("skipping"));
+
+    assert!(
+        output.child("b.txt").is_symlink(),
+        "Other file wasn't linked"
+    );
+    assert!(!output.child("a.txt").is_symlink(), "Conflict was replaced");
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+    cwd.close().unwrap();
+}
+
+#[test]
+fn conflict_backup() {
+    let (package, output, store) = setup();
+    let out_file = output.child("a.txt");
+
+    lash(
+        &package,
+        &output,
+        &store,
+        &["link", "--on-conflict", "backup", package.to_str().unwrap()],
+    )
+    .assert()
+    .success();
+
+    assert!(out_file.is_symlink(), "Link wasn't created");
+
+    // The backup is recorded in the store and kept in the backup area next to the store
+    let show = lash(
+        &package,
+        &output,
+        &store,
+        &["store", "show", out_file.to_str().unwrap()],
+    )
+    .assert()
+    .success();
+    let show = String::from_utf8(show.get_output().stdout.clone()).unwrap();
+    let backup = show
+        .lines()
+        .find_map(|l| l.strip_prefix("backup:"))
+        .expect("Backup wasn't recorded")
+        .trim()
+        .to_string();
+    assert!(backup.starts_with(store.child("backups").to_str().unwrap()));
+    assert_eq!(std::fs::read_to_string(&backup).unwrap(), "existing");
+
+    // Undo moves the original file back
+    lash(&package, &output, &store, &["undo"])
+        .assert()
+        .success();
+    assert!(
+        !std::path::Path::new(&backup).exists(),
+        "Backup wasn't moved back"
+    );
+    assert_eq!(std::fs::read_to_string(&out_file).unwrap(), "existing");
+
+    lash(&package, &output, &store, &["undo", "2"])
+        .assert()
+        .success();
+    assert!(out_file.is_symlink(), "Link wasn't created again");
+
+    // The backup is only restored when asked for
+    lash(
+        &package,
+        &output,
+        &store,
+        &["unlink", package.to_str().unwrap()],
+    )
+    .assert()
+    .success()
+    .stdout(contains("--restore"));
+    assert!(!out_file.exists(), "Backup was restored");
+
+    lash(&package, &output, &store, &["undo"])
+        .assert()
+        .success();
+    lash(
+        &package,
+        &output,
+        &store,
+        &["unlink", "--restore", package.to_str().unwrap()],
+    )
+    .assert()
+    .success();
+    assert!(!out_file.is_symlink(), "Link wasn't removed");
+    assert_eq!(std::fs::read_to_string(&out_file).unwrap(), "existing");
+    assert!(
+        std::fs::read_dir(store.child("backups"))
+            .unwrap()
+            .next()
+            .is_none(),
+        "Emptied backup directory wasn't removed"
+    );
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
+
+#[test]
+fn conflict_backup_directory() {
+    let (package, output, store) = setup();
+    package.child("sub/c.txt").write_str("c").unwrap();
+    output.child("sub").write_str("in the way").unwrap();
+
+    lash(
+        &package,
+        &output,
+        &store,
+        &["link", "--on-conflict", "backup", package.to_str().unwrap()],
+    )
+    .assert()
+    .success();
+
+    // The file is moved aside and the directory's contents are linked in its place
+    assert!(output.child("sub").is_dir(), "Directory wasn't created");
+    assert!(
+        output.child("sub/c.txt").is_symlink(),
+        "Directory contents weren't linked"
+    );
+    lash(
+        &package,
+        &output,
+        &store,
+        &["status", package.to_str().unwrap()],
+    )
+    .assert()
+    .success();
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
+
+#[test]
+fn conflict_overwrite() {
+    let (package, output, store) = setup();
+    output.child("dir").create_dir_all().unwrap();
+    package.child("dir").touch().unwrap();
+
+    // Directories are never overwritten
+    lash(
+        &package,
+        &output,
+        &store,
+        &[
+            "link",
+            "--on-conflict",
+            "overwrite",
+            package.to_str().unwrap(),
+        ],
+    )
+    .assert()
+    .failure();
+
+    std::fs::remove_dir(output.child("dir")).unwrap();
+
+    lash(
+        &package,
+        &output,
+        &store,
+        &[
+            "link",
+            "--on-conflict",
+            "overwrite",
+            package.to_str().unwrap(),
+        ],
+    )
+    .assert()
+    .success();
+
+    assert!(output.child("a.txt").is_symlink(), "Link wasn't created");
+    assert_eq!(
+        std::fs::read_to_string(package.child("a.txt")).unwrap(),
+        "package"
+    );
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
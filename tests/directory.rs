@@ -11,12 +11,14 @@ fn file_in_directory_created() {
     in_file.touch().unwrap();
 
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
     let out_dir = output.child("sub_dir");
     let out_file = output.child("sub_dir/file.txt");
 
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),
@@ -47,6 +49,7 @@ fn directory_with_file_is_removed() {
     in_file.touch().unwrap();
 
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
     let out_dir = output.child("sub_dir");
     let out_file = out_dir.child("file.txt");
 
@@ -62,6 +65,7 @@ fn directory_with_file_is_removed() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),
@@ -0,0 +1,55 @@
+use std::os::unix::fs::PermissionsExt;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+
+#[test]
+fn eject_package() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let in_file = package.child("sub_dir/a.sh");
+    let out_dir = output.child("sub_dir");
+    let out_file = out_dir.child("a.sh");
+
+    in_file.write_str("#!/bin/sh\n").unwrap();
+    std::fs::set_permissions(&in_file, std::fs::Permissions::from_mode(0o700)).unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(package.path())
+            .env("LASH_STORE", store.path())
+            .args(["--target", output.to_str().unwrap()])
+            .args(args);
+        cmd
+    };
+
+    lash(&["link", package.to_str().unwrap()])
+        .assert()
+        .success();
+    assert!(out_file.is_symlink());
+
+    lash(&["eject", package.to_str().unwrap()])
+        .assert()
+        .success();
+
+    assert!(!out_file.is_symlink(), "Link wasn't replaced");
+    assert_eq!(std::fs::read_to_string(&out_file).unwrap(), "#!/bin/sh\n");
+    assert_eq!(
+        std::fs::metadata(&out_file).unwrap().permissions().mode() & 0o777,
+        0o700
+    );
+    assert!(out_dir.is_dir(), "Directory was removed");
+    assert!(in_file.exists(), "Package was changed");
+    lash(&["store", "list"]).assert().success().stdout("");
+
+    // Nothing is left for unlink to remove
+    lash(&["unlink", package.to_str().unwrap()])
+        .assert()
+        .success();
+    assert!(out_file.exists(), "Ejected file was removed");
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
@@ -0,0 +1,116 @@
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::str::contains;
+
+#[test]
+fn fold_unfold_refold() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let a = root.child("a");
+    let b = root.child("b");
+    let share = output.child("share");
+
+    a.child("share/icons/a.png").write_str("a\n").unwrap();
+    a.child("share/icons/sub/x.png").write_str("x\n").unwrap();
+    b.child("share/icons/b.png").write_str("b\n").unwrap();
+    b.child("share/icons/sub/y.png").write_str("y\n").unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(root.path())
+            .env("LASH_STORE", store.path())
+            .args(["--target", output.to_str().unwrap(), "--fold"])
+            .args(args);
+        cmd
+    };
+
+    // The whole directory is linked when nothing is there yet
+    lash(&["link", "a"]).assert().success();
+    assert!(share.is_symlink());
+    assert_eq!(
+        share.canonicalize().unwrap(),
+        a.child("share").canonicalize().unwrap()
+    );
+    lash(&["store", "list"])
+        .assert()
+        .success()
+        .stdout(contains("folded"));
+    lash(&["status", "a"])
+        .assert()
+        .success()
+        .stdout(contains("a: 1 linked"));
+
+    // A second package splits the folded directories up
+    lash(&["link", "b"]).assert().success();
+    for dir in ["share", "share/icons", "share/icons/sub"] {
+        let dir = output.child(dir);
+        assert!(
+            dir.is_dir() && !dir.is_symlink(),
+            "{} wasn't unfolded",
+            dir.display()
+        );
+    }
+    for file in [
+        "share/icons/a.png",
+        "share/icons/b.png",
+        "share/icons/sub/x.png",
+        "share/icons/sub/y.png",
+    ] {
+        assert!(output.child(file).is_symlink(), "{} isn't linked", file);
+    }
+    assert!(!a.child("share/icons/b.png").exists(), "Package a changed");
+    lash(&["status", "a", "b"]).assert().success();
+
+    // Removing the second package folds the directories back up
+    lash(&["unlink", "b"]).assert().success();
+    assert!(share.is_symlink(), "share wasn't folded");
+    assert!(a.child("share/icons/sub/x.png").exists());
+    assert!(b.child("share/icons/b.png").exists(), "Package b changed");
+    lash(&["status", "a"])
+        .assert()
+        .success()
+        .stdout(contains("a: 1 linked"));
+
+    lash(&["unlink", "a"]).assert().success();
+    assert!(!share.exists() && !share.is_symlink());
+    lash(&["store", "list"]).assert().success().stdout("");
+
+    root.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
+
+#[test]
+fn fold_only_exact_directories() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+
+    package.child("plain/a.txt").write_str("a\n").unwrap();
+    package.child("ignored/a.txt").write_str("a\n").unwrap();
+    package.child("ignored/a.txt~").write_str("a\n").unwrap();
+    package.child("existing/a.txt").write_str("a\n").unwrap();
+    output.child("existing").create_dir_all().unwrap();
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .env("LASH_STORE", store.path())
+        .args(["--target", output.to_str().unwrap(), "--fold", "link"])
+        .arg(package.path())
+        .assert()
+        .success();
+
+    assert!(output.child("plain").is_symlink());
+    // Folding would link the ignored file
+    assert!(!output.child("ignored").is_symlink());
+    assert!(output.child("ignored/a.txt").is_symlink());
+    assert!(!output.child("ignored/a.txt~").exists());
+    // Existing directories are never replaced
+    assert!(!output.child("existing").is_symlink());
+    assert!(output.child("existing/a.txt").is_symlink());
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
@@ -0,0 +1,88 @@
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::str::contains;
+
+#[test]
+fn hooks() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let package = root.child("package");
+    let log = root.child("hooks.log");
+
+    package.child("a.txt").write_str("a\n").unwrap();
+
+    // Hooks from the workarea configuration
+    root.child("lash.toml")
+        .write_str(&format!(
+            "[hooks]\n\
+             pre_link = \"echo $LASH_HOOK $LASH_PACKAGE $LASH_TARGET >> {log}\"\n\
+             post_link = \"echo $LASH_HOOK $LASH_PATHS >> {log}\"\n\
+             post_unlink = \"echo $LASH_HOOK >> {log}\"\n",
+            log = log.display()
+        ))
+        .unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(root.path())
+            .env("LASH_STORE", store.path())
+            .args(["--target", output.to_str().unwrap()])
+            .args(args);
+        cmd
+    };
+
+    lash(&["link", "package"]).assert().success();
+    assert!(output.child("a.txt").is_symlink());
+
+    let package_path = package.canonicalize().unwrap();
+    let output_path = output.canonicalize().unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&log).unwrap(),
+        format!(
+            "pre-link {} {}\npost-link {}\n",
+            package_path.display(),
+            output_path.display(),
+            output_path.join("a.txt").display()
+        )
+    );
+
+    // Nothing changes so the hooks aren't run
+    lash(&["relink", "package"]).assert().success();
+    assert_eq!(std::fs::read_to_string(&log).unwrap().lines().count(), 2);
+
+    // Hooks are printed but not run in a dry run
+    lash(&["-n", "unlink", "package"])
+        .assert()
+        .success()
+        .stdout(contains("Running post-unlink hook"));
+    assert_eq!(std::fs::read_to_string(&log).unwrap().lines().count(), 2);
+
+    // A failing pre hook from the package configuration stops the package being changed
+    package
+        .child("lash.toml")
+        .write_str("[hooks]\npre_unlink = \"exit 1\"\n")
+        .unwrap();
+    lash(&["unlink", "package"])
+        .assert()
+        .failure()
+        .stderr(contains("pre-unlink hook failed"));
+    assert!(output.child("a.txt").is_symlink(), "Package was unlinked");
+    assert_eq!(std::fs::read_to_string(&log).unwrap().lines().count(), 2);
+
+    package
+        .child("lash.toml")
+        .write_str("[hooks]\npre_unlink = \"true\"\n")
+        .unwrap();
+    lash(&["unlink", "package"]).assert().success();
+    assert!(!output.child("a.txt").exists());
+    assert!(
+        std::fs::read_to_string(&log)
+            .unwrap()
+            .ends_with("post-unlink\n")
+    );
+
+    root.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
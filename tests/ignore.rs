@@ -0,0 +1,108 @@
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+
+fn lash(
+    cwd: &assert_fs::TempDir,
+    output: &assert_fs::TempDir,
+    store: &assert_fs::TempDir,
+    args: &[&str],
+) -> Command {
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    cmd.current_dir(cwd.path())
+        .env("LASH_STORE", store.path())
+        .args(["--target", output.to_str().unwrap()])
+        .args(args);
+    cmd
+}
+
+#[test]
+fn ignore_patterns() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let cwd = assert_fs::TempDir::new().unwrap();
+
+    for file in [
+        ".git/config",
+        "README.md",
+        "docs/README.md",
+        "LICENSE",
+        "a.txt",
+        "a.txt~",
+        "build/out.o",
+        "src/build",
+        "notes.md",
+        "keep.md",
+        "local/secret",
+        "b.log",
+    ] {
+        package.child(file).write_str("content\n").unwrap();
+    }
+
+    // Directory-only, anchored and negated patterns
+    package
+        .child(".lashignore")
+        .write_str("build/\n*.md\n!keep.md\n")
+        .unwrap();
+    cwd.child("lash.toml")
+        .write_str("ignore = [\"/local\"]\n")
+        .unwrap();
+
+    lash(
+        &cwd,
+        &output,
+        &store,
+        &["--ignore", "*.log", "link", package.to_str().unwrap()],
+    )
+    .assert()
+    .success();
+
+    for linked in ["a.txt", "src/build", "keep.md"] {
+        assert!(
+            output.child(linked).is_symlink(),
+            "{} wasn't linked",
+            linked
+        );
+    }
+    for ignored in [
+        ".git",
+        ".lashignore",
+        "README.md",
+        "docs/README.md",
+        "LICENSE",
+        "a.txt~",
+        "build",
+        "notes.md",
+        "local",
+        "b.log",
+    ] {
+        assert!(
+            !output.child(ignored).exists(),
+            "{} wasn't ignored",
+            ignored
+        );
+    }
+
+    // The defaults can be overridden
+    lash(
+        &cwd,
+        &output,
+        &store,
+        &["--ignore", "!/README*", "relink", package.to_str().unwrap()],
+    )
+    .assert()
+    .success();
+    assert!(
+        output.child("README.md").is_symlink(),
+        "README wasn't linked"
+    );
+    assert!(
+        !output.child("docs/README.md").exists(),
+        "Nested README wasn't ignored"
+    );
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+    cwd.close().unwrap();
+}
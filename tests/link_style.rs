@@ -0,0 +1,104 @@
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+use predicates::str::contains;
+
+#[test]
+fn link_style() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let home = root.child("home");
+    let package = home.child("dotfiles/package");
+    let file = home.child("a.txt");
+    let nested = home.child("sub/b.txt");
+
+    package.child("a.txt").write_str("a\n").unwrap();
+    package.child("sub/b.txt").write_str("b\n").unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(root.path())
+            .env("LASH_STORE", store.path())
+            .args(["--target", home.to_str().unwrap()])
+            .args(args);
+        cmd
+    };
+
+    lash(&["--link-style", "relative", "link", "home/dotfiles/package"])
+        .assert()
+        .success();
+
+    assert_eq!(
+        file.read_link().unwrap(),
+        std::path::Path::new("dotfiles/package/a.txt")
+    );
+    assert_eq!(
+        nested.read_link().unwrap(),
+        std::path::Path::new("../dotfiles/package/sub/b.txt")
+    );
+    assert_eq!(std::fs::read_to_string(&nested).unwrap(), "b\n");
+
+    // The links are already relative so nothing is rewritten
+    lash(&[
+        "--link-style",
+        "relative",
+        "-n",
+        "relink",
+        "home/dotfiles/package",
+    ])
+    .assert()
+    .success()
+    .stdout(contains("Rewrite").not());
+
+    // Status compares the links with the style they were created in unless another is chosen
+    lash(&["status", "home/dotfiles/package"])
+        .assert()
+        .success();
+    lash(&[
+        "--link-style",
+        "absolute",
+        "status",
+        "home/dotfiles/package",
+    ])
+    .assert()
+    .failure()
+    .stdout(contains("relative"));
+
+    // Changing the style rewrites the links
+    lash(&["relink", "home/dotfiles/package"])
+        .assert()
+        .success()
+        .stdout(contains("Rewrite"));
+    assert!(file.read_link().unwrap().is_absolute());
+    assert!(nested.read_link().unwrap().is_absolute());
+
+    // The style can be set in the configuration file
+    root.child("lash.toml")
+        .write_str("link_style = \"relative\"\n")
+        .unwrap();
+    lash(&["status", "home/dotfiles/package"])
+        .assert()
+        .failure()
+        .stdout(contains("absolute"));
+    lash(&["relink", "home/dotfiles/package"])
+        .assert()
+        .success();
+    assert!(file.read_link().unwrap().is_relative());
+    lash(&["status", "home/dotfiles/package"])
+        .assert()
+        .success();
+
+    // Relative links to removed files are cleaned up
+    std::fs::remove_file(package.child("sub/b.txt")).unwrap();
+    lash(&["link", "home/dotfiles/package"]).assert().success();
+    assert!(!nested.is_symlink(), "Zombie link wasn't removed");
+
+    lash(&["unlink", "home/dotfiles/package"])
+        .assert()
+        .success();
+    assert!(!file.exists() && !file.is_symlink());
+    assert!(!home.child("sub").exists());
+
+    root.close().unwrap();
+    store.close().unwrap();
+}
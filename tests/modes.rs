@@ -0,0 +1,113 @@
+use std::os::unix::fs::MetadataExt;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+use predicates::str::contains;
+
+#[test]
+fn install_modes() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let package = root.child("package");
+    let copy = output.child("a.txt");
+    let hardlink = output.child("sub/b.conf");
+
+    package.child("a.txt").write_str("a\n").unwrap();
+    package.child("sub/b.conf").write_str("b\n").unwrap();
+    package
+        .child("lash.toml")
+        .write_str("[modes]\n\"*.conf\" = \"hardlink\"\n")
+        .unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(root.path())
+            .env("LASH_STORE", store.path())
+            .args(["--target", output.to_str().unwrap()])
+            .args(args);
+        cmd
+    };
+
+    lash(&["--mode", "copy", "link", "package"])
+        .assert()
+        .success();
+    assert!(copy.is_file() && !copy.is_symlink());
+    assert_eq!(std::fs::read_to_string(&copy).unwrap(), "a\n");
+    assert_eq!(
+        hardlink.metadata().unwrap().ino(),
+        package.child("sub/b.conf").metadata().unwrap().ino()
+    );
+    lash(&["store", "list"])
+        .assert()
+        .success()
+        .stdout(contains("copy"))
+        .stdout(contains("hardlink"));
+    lash(&["--mode", "copy", "status", "package"])
+        .assert()
+        .success();
+
+    // Copies of changed package files are updated
+    package.child("a.txt").write_str("a2\n").unwrap();
+    lash(&["--mode", "copy", "status", "package"])
+        .assert()
+        .failure()
+        .stdout(contains("outdated"));
+    lash(&["--mode", "copy", "relink", "package"])
+        .assert()
+        .success()
+        .stdout(contains("Replace"));
+    assert_eq!(std::fs::read_to_string(&copy).unwrap(), "a2\n");
+
+    // Changed copies are left alone
+    copy.write_str("local\n").unwrap();
+    lash(&["--mode", "copy", "status", "package"])
+        .assert()
+        .failure()
+        .stdout(contains("modified"));
+    lash(&["--mode", "copy", "relink", "package"])
+        .assert()
+        .success()
+        .stdout(contains("has been changed"));
+    assert_eq!(std::fs::read_to_string(&copy).unwrap(), "local\n");
+
+    lash(&["--mode", "copy", "unlink", "package"])
+        .assert()
+        .success()
+        .stdout(contains("leaving it in place"));
+    assert_eq!(std::fs::read_to_string(&copy).unwrap(), "local\n");
+    assert!(!hardlink.exists(), "Hard link wasn't removed");
+    assert!(package.child("sub/b.conf").exists(), "Package file removed");
+    lash(&["store", "list"]).assert().success().stdout("");
+    std::fs::remove_file(&copy).unwrap();
+
+    // Changing the mode replaces the installed files
+    lash(&["link", "package"]).assert().success();
+    assert!(copy.is_symlink());
+    lash(&["--mode", "copy", "status", "package"])
+        .assert()
+        .failure()
+        .stdout(contains("outdated"));
+    lash(&["--mode", "copy", "relink", "package"])
+        .assert()
+        .success();
+    assert!(copy.is_file() && !copy.is_symlink());
+    lash(&["relink", "package"]).assert().success();
+    assert!(copy.is_symlink());
+
+    // Unchanged copies of removed package files are cleaned up
+    lash(&["--mode", "copy", "relink", "package"])
+        .assert()
+        .success();
+    std::fs::remove_file(package.child("a.txt")).unwrap();
+    lash(&["--mode", "copy", "relink", "package"])
+        .assert()
+        .success()
+        .stdout(contains("has been changed").not());
+    assert!(!copy.exists(), "Zombie copy wasn't removed");
+
+    root.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
@@ -12,10 +12,12 @@ fn no_args_print_usage() {
 fn link_all_options_long() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
 
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--dotfiles",
             "--dry-run",
@@ -31,16 +33,19 @@ fn link_all_options_long() {
 
     package.close().unwrap();
     output.close().unwrap();
+    store.close().unwrap();
 }
 
 #[test]
 fn link_all_options_short() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
 
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "-n",
             "-v",
@@ -54,16 +59,19 @@ fn link_all_options_short() {
 
     package.close().unwrap();
     output.close().unwrap();
+    store.close().unwrap();
 }
 
 #[test]
 fn unlink_all_options_long() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
 
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--dotfiles",
             "--dry-run",
@@ -78,16 +86,19 @@ fn unlink_all_options_long() {
 
     package.close().unwrap();
     output.close().unwrap();
+    store.close().unwrap();
 }
 
 #[test]
 fn unlink_all_options_short() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
 
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "-n",
             "-v",
@@ -101,4 +112,5 @@ fn unlink_all_options_short() {
 
     package.close().unwrap();
     output.close().unwrap();
+    store.close().unwrap();
 }
@@ -0,0 +1,95 @@
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::str::contains;
+
+#[test]
+fn package_config() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let home = root.child("home");
+    let bin = root.child("packages/bin");
+    let config = root.child("packages/config");
+    let shell = root.child("packages/shell");
+
+    bin.child("hello").write_str("#!/bin/sh\n").unwrap();
+    bin.child("lash.toml")
+        .write_str(&format!(
+            "description = \"Scripts\"\ntarget = \"{}\"\n",
+            home.child(".local/bin").display()
+        ))
+        .unwrap();
+
+    config.child("dot-app/app.conf").write_str("a\n").unwrap();
+    config
+        .child("dot-app/app.conf.bak")
+        .write_str("a\n")
+        .unwrap();
+    config
+        .child("lash.toml")
+        .write_str("target = \"../../home/.config\"\ndotfiles = true\nignore = [\"*.bak\"]\n")
+        .unwrap();
+
+    shell.child("dot-profile").write_str("b\n").unwrap();
+    home.child(".local/bin").create_dir_all().unwrap();
+    home.child(".config").create_dir_all().unwrap();
+
+    // The workarea configuration provides the defaults for the packages
+    root.child("lash.toml")
+        .write_str(&format!("target = \"{}\"\n", home.display()))
+        .unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(root.path())
+            .env("LASH_STORE", store.path())
+            .args(args);
+        cmd
+    };
+
+    lash(&["link", "packages/bin", "packages/config", "packages/shell"])
+        .assert()
+        .success()
+        .stdout(contains("Scripts"));
+
+    assert!(home.child(".local/bin/hello").is_symlink());
+    assert!(home.child(".config/.app/app.conf").is_symlink());
+    assert!(!home.child(".config/.app/app.conf.bak").exists());
+    assert!(home.child("dot-profile").is_symlink());
+    for package in ["", ".local/bin/", ".config/"] {
+        assert!(
+            !home.child(format!("{}lash.toml", package)).exists(),
+            "Package configuration was linked"
+        );
+    }
+
+    // Every package is checked in its own target directory
+    lash(&["status"])
+        .assert()
+        .success()
+        .stdout(contains("packages/bin: 1 linked"))
+        .stdout(contains("packages/config: 2 linked"))
+        .stdout(contains("packages/shell: 1 linked"));
+
+    // The command line takes priority over the package configuration
+    let other = assert_fs::TempDir::new().unwrap();
+    lash(&["--target", other.to_str().unwrap(), "link", "packages/bin"])
+        .assert()
+        .success();
+    assert!(other.child("hello").is_symlink());
+
+    lash(&[
+        "unlink",
+        "packages/bin",
+        "packages/config",
+        "packages/shell",
+    ])
+    .assert()
+    .success();
+    assert!(!home.child(".local/bin/hello").exists());
+    assert!(!home.child(".config/.app").exists());
+    assert!(!home.child("dot-profile").exists());
+
+    root.close().unwrap();
+    store.close().unwrap();
+    other.close().unwrap();
+}
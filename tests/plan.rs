@@ -0,0 +1,83 @@
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+
+/// Get the actions printed by lash, without the log timestamps and colours.
+fn actions(output: &[u8]) -> Vec<String> {
+    String::from_utf8(output.to_vec())
+        .unwrap()
+        .lines()
+        .filter_map(|l| l.split_once("] ").map(|(_, msg)| msg))
+        .map(|msg| msg.rsplit("\u{1b}[0m").next().unwrap().to_string())
+        .filter(|msg| msg.starts_with("Create") || msg.starts_with("Remove"))
+        .collect()
+}
+
+#[test]
+fn dry_run_matches_run() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let in_zombie = package.child("sub_dir/nested/zombie.txt");
+    let in_file = package.child("file.txt");
+    let out_dir = output.child("sub_dir");
+    let out_zombie = output.child("sub_dir/nested/zombie.txt");
+
+    in_zombie.touch().unwrap();
+    in_file.touch().unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(package.path())
+            .env("LASH_STORE", store.path())
+            .args(args);
+        cmd
+    };
+
+    lash(&[
+        "--target",
+        output.to_str().unwrap(),
+        "link",
+        package.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    // Leave a zombie link behind in nested directories that are only removable once the link
+    // has been removed
+    std::fs::remove_dir_all(package.child("sub_dir").path()).unwrap();
+
+    let dry_run = lash(&[
+        "--dry-run",
+        "--target",
+        output.to_str().unwrap(),
+        "unlink",
+        package.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+    let planned = actions(&dry_run.get_output().stdout);
+
+    assert!(out_zombie.is_symlink(), "Dry run removed the zombie link");
+    assert!(out_dir.exists(), "Dry run removed the directory");
+    assert_eq!(planned.len(), 4, "Unexpected plan {:?}", planned);
+
+    let run = lash(&[
+        "--target",
+        output.to_str().unwrap(),
+        "unlink",
+        package.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    assert_eq!(planned, actions(&run.get_output().stdout));
+    assert!(!out_dir.exists(), "Zombie directories weren't removed");
+    assert!(
+        !output.child("file.txt").exists(),
+        "Link to file wasn't removed"
+    );
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
@@ -0,0 +1,90 @@
+use std::os::unix::fs::MetadataExt;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+use predicates::str::contains;
+
+#[test]
+fn diff_and_pull() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let package = root.child("package");
+    let copy = output.child("a.txt");
+    let hardlink = output.child("b.txt");
+
+    package.child("a.txt").write_str("one\ntwo\n").unwrap();
+    package.child("b.txt").write_str("b\n").unwrap();
+    package
+        .child("lash.toml")
+        .write_str("mode = \"copy\"\n[modes]\n\"b.txt\" = \"hardlink\"\n")
+        .unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(root.path())
+            .env("LASH_STORE", store.path())
+            .args(["--target", output.to_str().unwrap()])
+            .args(args);
+        cmd
+    };
+
+    lash(&["link", "package"]).assert().success();
+    lash(&["diff"]).assert().success().stdout("");
+
+    // Changes to the copy are shown and pulled into the package
+    copy.write_str("one\nthree\n").unwrap();
+    lash(&["diff", "package"])
+        .assert()
+        .success()
+        .stdout(contains("-two"))
+        .stdout(contains("+three"));
+    lash(&["-n", "pull"])
+        .assert()
+        .success()
+        .stdout(contains("Pulling"));
+    package.child("a.txt").assert("one\ntwo\n");
+    lash(&["pull", "package"]).assert().success();
+    package.child("a.txt").assert("one\nthree\n");
+    lash(&["diff"]).assert().success().stdout("");
+    lash(&["status", "package"]).assert().success();
+
+    // Files changed on both sides need --force
+    copy.write_str("target\n").unwrap();
+    package.child("a.txt").write_str("package\n").unwrap();
+    lash(&["pull", "package"])
+        .assert()
+        .failure()
+        .stderr(contains("--force"));
+    package.child("a.txt").assert("package\n");
+    lash(&["pull", "--force", copy.to_str().unwrap()])
+        .assert()
+        .success();
+    package.child("a.txt").assert("target\n");
+
+    // Hard links replaced by an editor are pulled and linked again by relink
+    std::fs::remove_file(&hardlink).unwrap();
+    hardlink.write_str("edited\n").unwrap();
+    lash(&["diff"])
+        .assert()
+        .success()
+        .stdout(contains("+edited"))
+        .stdout(contains("a.txt").not());
+    lash(&["pull"]).assert().success();
+    package.child("b.txt").assert("edited\n");
+    lash(&["status", "package"])
+        .assert()
+        .failure()
+        .stdout(contains("outdated"));
+    lash(&["relink", "package"]).assert().success();
+    assert_eq!(
+        hardlink.metadata().unwrap().ino(),
+        package.child("b.txt").metadata().unwrap().ino()
+    );
+    lash(&["status", "package"]).assert().success();
+
+    root.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
@@ -0,0 +1,60 @@
+use std::os::unix::fs::MetadataExt;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+
+#[test]
+fn relink_only_changes_stale_links() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let keep = package.child("keep.txt");
+    let dot_dir = package.child("dot-config");
+    let dot_file = dot_dir.child("a.txt");
+    let out_keep = output.child("keep.txt");
+    let out_dot_dir = output.child("dot-config");
+    let out_dir = output.child(".config");
+
+    keep.touch().unwrap();
+    dot_file.touch().unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(package.path())
+            .env("LASH_STORE", store.path())
+            .args(["--target", output.to_str().unwrap()])
+            .args(args);
+        cmd
+    };
+
+    lash(&["link", package.to_str().unwrap()])
+        .assert()
+        .success();
+    assert!(out_dot_dir.child("a.txt").is_symlink());
+    let inode = std::fs::symlink_metadata(&out_keep).unwrap().ino();
+
+    // Changing the name mapping leaves the old links behind when only linking
+    lash(&["--dotfiles", "restow", package.to_str().unwrap()])
+        .assert()
+        .success();
+
+    assert!(!out_dot_dir.exists(), "Stale directory wasn't removed");
+    assert!(
+        out_dir.child("a.txt").is_symlink(),
+        "New link wasn't created"
+    );
+    assert_eq!(out_dir.child("a.txt").read_link().unwrap(), dot_file.path());
+    assert_eq!(
+        std::fs::symlink_metadata(&out_keep).unwrap().ino(),
+        inode,
+        "Correct link was recreated"
+    );
+
+    lash(&["--dotfiles", "status", package.to_str().unwrap()])
+        .assert()
+        .success();
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
@@ -0,0 +1,120 @@
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::str::contains;
+
+#[test]
+fn rename_rules() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    // The target directory itself is never renamed
+    let output = root.child("dot-home");
+    let package = root.child("package");
+
+    output.create_dir_all().unwrap();
+    package.child("dot-bashrc").write_str("a\n").unwrap();
+    package.child("my-dot-file").write_str("b\n").unwrap();
+    package
+        .child("dot_config/app.conf.tmpl")
+        .write_str("c\n")
+        .unwrap();
+    package.child("prompt.local").write_str("d\n").unwrap();
+    root.child("lash.toml")
+        .write_str("rename = [{ prefix = \"dot_\", replace = \".\" }, { suffix = \".tmpl\" }]\n")
+        .unwrap();
+    package
+        .child("lash.toml")
+        .write_str(
+            "[[rename]]\n\
+             regex = '^(.*)\\.local$'\n\
+             replace = '$1-local'\n\
+             reverse = { regex = '^(.*)-local$', replace = '$1.local' }\n",
+        )
+        .unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(root.path())
+            .env("LASH_STORE", store.path())
+            .args(["--dotfiles", "--target", output.to_str().unwrap()])
+            .args(args);
+        cmd
+    };
+
+    lash(&["link", "package"]).assert().success();
+    for path in [".bashrc", "my-dot-file", ".config/app.conf", "prompt-local"] {
+        assert!(output.child(path).is_symlink(), "{} isn't linked", path);
+    }
+    assert!(!root.child(".home").exists());
+    lash(&["status", "package"]).assert().success();
+
+    // Adding reverses the rules
+    output.child(".profile").write_str("e\n").unwrap();
+    output.child(".config/other.conf").write_str("f\n").unwrap();
+    output.child("theme-local").write_str("g\n").unwrap();
+    lash(&["add", "--package", "package"])
+        .args([
+            output.child(".profile").path(),
+            output.child(".config/other.conf").path(),
+            output.child("theme-local").path(),
+        ])
+        .assert()
+        .success();
+    // Both prefix rules give ".", the last one is reversed
+    package.child("dot_profile").assert("e\n");
+    package.child("dot_config/other.conf").assert("f\n");
+    package.child("theme.local").assert("g\n");
+    assert!(output.child("theme-local").is_symlink());
+
+    // Names the rules can't produce can't be added
+    output.child("notes.tmpl").write_str("h\n").unwrap();
+    lash(&["add", "--package", "package"])
+        .arg(output.child("notes.tmpl").path())
+        .assert()
+        .failure()
+        .stderr(contains("can't be reversed"));
+    assert!(!package.child("notes.tmpl").exists());
+
+    lash(&["unlink", "package"]).assert().success();
+    assert!(!output.child(".bashrc").exists());
+    assert!(!output.child(".config").exists());
+
+    root.close().unwrap();
+    store.close().unwrap();
+}
+
+#[test]
+fn non_utf8_names() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let output = root.child("home");
+    let package = root.child("package");
+    let name = OsStr::from_bytes(b"caf\xe9.txt");
+
+    output.create_dir_all().unwrap();
+    package.create_dir_all().unwrap();
+    std::fs::write(package.path().join(name), "a\n").unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(root.path())
+            .env("LASH_STORE", store.path())
+            .args(["--target", output.to_str().unwrap()])
+            .args(args);
+        cmd
+    };
+
+    // Names no rule applies to are linked as they are, with or without rules
+    for args in [&[][..], &["--dotfiles"][..]] {
+        lash(args).args(["link", "package"]).assert().success();
+        assert!(output.path().join(name).is_symlink(), "File wasn't linked");
+        lash(args).args(["status", "package"]).assert().success();
+        lash(args).args(["unlink", "package"]).assert().success();
+        assert!(!output.path().join(name).exists(), "Link wasn't removed");
+    }
+
+    root.close().unwrap();
+    store.close().unwrap();
+}
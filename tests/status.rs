@@ -0,0 +1,133 @@
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+
+#[test]
+fn status_reports_each_state() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let linked = package.child("linked.txt");
+    let missing = package.child("missing.txt");
+    let relative = package.child("relative.txt");
+    let zombie = package.child("zombie.txt");
+    let conflict = package.child("conflict.txt");
+    let new = package.child("new.txt");
+
+    for file in [&linked, &missing, &relative, &zombie] {
+        file.touch().unwrap();
+    }
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(package.path())
+            .env("LASH_STORE", store.path())
+            .args(["--target", output.to_str().unwrap()])
+            .args(args);
+        cmd
+    };
+
+    lash(&["link", package.to_str().unwrap()])
+        .assert()
+        .success();
+    lash(&["status", package.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(format!("{}: 4 linked\n", package.path().display()));
+
+    std::fs::remove_file(output.child("missing.txt")).unwrap();
+    std::fs::remove_file(output.child("relative.txt")).unwrap();
+    output
+        .child("relative.txt")
+        .symlink_to_file(relative_path(output.path(), relative.path()).join("relative.txt"))
+        .unwrap();
+    std::fs::remove_file(&zombie).unwrap();
+    conflict.touch().unwrap();
+    output.child("conflict.txt").touch().unwrap();
+    new.touch().unwrap();
+
+    let out = |name: &str| output.child(name).path().display().to_string();
+    let expected = format!(
+        "{}: 1 linked, 1 missing, 1 conflict, 1 zombie, 1 relative, 1 new\n\
+         conflict\t{}\nmissing\t{}\nnew\t{}\nrelative\t{}\nzombie\t{}\n",
+        package.path().display(),
+        out("conflict.txt"),
+        out("missing.txt"),
+        out("new.txt"),
+        out("relative.txt"),
+        out("zombie.txt"),
+    );
+
+    // Without any packages every package in the store for the target is checked
+    lash(&["status"])
+        .assert()
+        .failure()
+        .stdout(expected.clone());
+    lash(&["status", package.to_str().unwrap()])
+        .assert()
+        .failure()
+        .stdout(expected);
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
+
+#[test]
+fn status_uses_recorded_options() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+
+    package.child("dot-bashrc").touch().unwrap();
+    package.child("a.txt").write_str("a").unwrap();
+    package.child("b.txt").touch().unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(package.path())
+            .env("LASH_STORE", store.path())
+            .args(["--target", output.to_str().unwrap()])
+            .args(args);
+        cmd
+    };
+
+    lash(&[
+        "--dotfiles",
+        "--link-style",
+        "relative",
+        "link",
+        package.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+    std::fs::remove_file(output.child("a.txt")).unwrap();
+    lash(&[
+        "--dotfiles",
+        "--mode",
+        "copy",
+        "link",
+        package.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+    assert!(output.child("a.txt").is_file() && !output.child("a.txt").is_symlink());
+
+    // Each path is compared with the options it was installed with
+    lash(&["status", package.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(format!("{}: 3 linked\n", package.path().display()));
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
+
+/// Get a relative path from the directory `from` to the directory containing `to`.
+fn relative_path(from: &std::path::Path, to: &std::path::Path) -> std::path::PathBuf {
+    let mut path = std::path::PathBuf::new();
+    for _ in from.components().skip(1) {
+        path.push("..");
+    }
+    path.join(to.parent().unwrap().strip_prefix("/").unwrap())
+}
@@ -1,10 +1,15 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
 use assert_cmd::Command;
 use assert_fs::prelude::*;
+use lash::store::{EntryKind, STORE_VERSION, Store};
 
 #[test]
 fn handle_missing_link() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
     let in_file = package.child("a.txt");
     let out_file = output.child("a.txt");
 
@@ -13,6 +18,7 @@ fn handle_missing_link() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),
@@ -35,6 +41,7 @@ fn handle_missing_link() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),
@@ -46,4 +53,392 @@ fn handle_missing_link() {
 
     package.close().unwrap();
     output.close().unwrap();
+    store.close().unwrap();
+}
+
+#[test]
+fn migrate_unversioned_store() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let in_file = package.child("zombie.txt");
+    let out_zombie = output.child("zombie.txt");
+    let linked_file = package.child("a.txt");
+    let store_file = store.child("store.bin");
+
+    linked_file.touch().unwrap();
+
+    // Write a store in the layout used before the store was versioned and a zombie link that
+    // only that store knows about
+    let legacy: HashMap<PathBuf, PathBuf> =
+        HashMap::from([(out_zombie.to_path_buf(), in_file.to_path_buf())]);
+    let data = bincode::encode_to_vec(&legacy, bincode::config::standard()).unwrap();
+    store_file.write_binary(&data).unwrap();
+    out_zombie.symlink_to_file(in_file.path()).unwrap();
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .current_dir(package.path())
+        .env("LASH_STORE", store.path())
+        .args([
+            "--target",
+            output.to_str().unwrap(),
+            "link",
+            package.to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+
+    assert!(!out_zombie.is_symlink(), "Zombie link wasn't removed!");
+
+    let loaded = Store::load(store_file.path()).unwrap();
+    assert_eq!(loaded.version, STORE_VERSION);
+    assert!(
+        !loaded.entries.contains_key(out_zombie.path()),
+        "Zombie is still in the store"
+    );
+
+    let entry = &loaded.entries[output.child("a.txt").path()];
+    assert_eq!(entry.source, linked_file.path());
+    assert_eq!(entry.kind, EntryKind::Link);
+    assert_eq!(entry.package.as_deref(), Some(package.path()));
+    assert_eq!(entry.target_root.as_deref(), Some(output.path()));
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
+
+#[test]
+fn wait_for_store_lock() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let in_file = package.child("a.txt");
+    let out_file = output.child("a.txt");
+
+    in_file.touch().unwrap();
+
+    // Hold the lock as if another lash process was running
+    let lock = std::fs::File::create(store.child("store.lock").path()).unwrap();
+    lock.lock().unwrap();
+
+    let mut child =
+        std::process::Command::new(assert_cmd::cargo::cargo_bin(env!("CARGO_PKG_NAME")))
+            .current_dir(package.path())
+            .env("LASH_STORE", store.path())
+            .args([
+                "--target",
+                output.to_str().unwrap(),
+                "link",
+                package.to_str().unwrap(),
+            ])
+            .spawn()
+            .unwrap();
+
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    assert!(
+        child.try_wait().unwrap().is_none(),
+        "Lash didn't wait for the lock"
+    );
+    assert!(!out_file.is_symlink(), "Link created while locked");
+
+    drop(lock);
+    assert!(child.wait().unwrap().success());
+
+    assert!(out_file.is_symlink(), "Link wasn't created after unlocking");
+    assert!(
+        !store.child("store.tmp").exists(),
+        "Temporary store was left behind"
+    );
+    assert!(Store::load(store.child("store.bin").path()).is_ok());
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
+
+#[test]
+fn store_subcommands() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let in_file = package.child("a.txt");
+    let out_file = output.child("a.txt");
+    let export_file = output.child("export.toml");
+
+    in_file.touch().unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(package.path())
+            .env("LASH_STORE", store.path())
+            .args(args);
+        cmd
+    };
+
+    lash(&[
+        "--target",
+        output.to_str().unwrap(),
+        "link",
+        package.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    lash(&["store", "path"])
+        .assert()
+        .success()
+        .stdout(format!("{}\n", store.child("store.bin").display()));
+    lash(&["--profile", "other", "store", "path"])
+        .assert()
+        .success()
+        .stdout(format!(
+            "{}\n",
+            store.child("profiles/other/store.bin").display()
+        ));
+
+    let expected = format!(
+        "link\t{}\t{}\n",
+        out_file.path().display(),
+        in_file.path().display()
+    );
+    lash(&["store", "list", "--package", package.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(expected.clone());
+    lash(&["store", "list", "--prefix", package.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout("");
+
+    lash(&["store", "show", out_file.to_str().unwrap()])
+        .assert()
+        .success();
+    lash(&["store", "show", in_file.to_str().unwrap()])
+        .assert()
+        .failure();
+
+    lash(&["store", "export", export_file.to_str().unwrap()])
+        .assert()
+        .success();
+    let exported = std::fs::read_to_string(export_file.path()).unwrap();
+    assert!(
+        exported.contains(out_file.to_str().unwrap()),
+        "Export doesn't contain the link"
+    );
+
+    lash(&["store", "forget", package.to_str().unwrap()])
+        .assert()
+        .success();
+    lash(&["store", "list"]).assert().success().stdout("");
+    assert!(out_file.is_symlink(), "Forget changed the file system");
+
+    lash(&["store", "import", export_file.to_str().unwrap()])
+        .assert()
+        .success();
+    lash(&["store", "list"]).assert().success().stdout(expected);
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
+
+#[test]
+fn rebuild_corrupted_store() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let in_dir = package.child("sub_dir");
+    let in_file = in_dir.child("a.txt");
+    let in_zombie = package.child("zombie.txt");
+    let out_dir = output.child("sub_dir");
+    let out_file = out_dir.child("a.txt");
+    let out_zombie = output.child("zombie.txt");
+    let foreign_file = output.child("foreign.txt");
+    let store_file = store.child("store.bin");
+
+    in_file.touch().unwrap();
+    in_zombie.touch().unwrap();
+    foreign_file.touch().unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(package.path())
+            .env("LASH_STORE", store.path())
+            .args(args);
+        cmd
+    };
+
+    lash(&[
+        "--target",
+        output.to_str().unwrap(),
+        "link",
+        package.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    std::fs::remove_file(&in_zombie).unwrap();
+    store_file.write_binary(b"not a store").unwrap();
+
+    lash(&["store", "list"]).assert().failure();
+
+    // A dry run leaves the corrupted store where it is
+    lash(&[
+        "--dry-run",
+        "store",
+        "rebuild",
+        "--target",
+        output.to_str().unwrap(),
+        package.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+    assert!(
+        !store.child("store.corrupt").exists(),
+        "Dry run moved the corrupted store"
+    );
+    store_file.assert("not a store");
+
+    lash(&[
+        "store",
+        "rebuild",
+        "--target",
+        output.to_str().unwrap(),
+        package.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    assert!(
+        store.child("store.corrupt").exists(),
+        "Corrupted store wasn't kept"
+    );
+
+    let loaded = Store::load(store_file.path()).unwrap();
+    assert_eq!(loaded.entries.len(), 3);
+    assert_eq!(loaded.entries[out_dir.path()].kind, EntryKind::Directory);
+    assert_eq!(loaded.entries[out_file.path()].source, in_file.path());
+    assert_eq!(loaded.entries[out_zombie.path()].source, in_zombie.path());
+
+    // The rebuilt store should know about the zombie link so it can be removed
+    lash(&[
+        "--target",
+        output.to_str().unwrap(),
+        "link",
+        package.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    assert!(!out_zombie.is_symlink(), "Zombie link wasn't removed!");
+    assert!(out_file.is_symlink(), "Link was removed");
+    assert!(foreign_file.exists(), "Foreign file was removed");
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
+
+#[test]
+fn export_import_copies() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let out_file = output.child("a.txt");
+    let export_file = store.child("export.toml");
+
+    // Contents hashing to more than i64::MAX, which TOML integers can't hold
+    package.child("a.txt").write_str("a").unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(package.path())
+            .env("LASH_STORE", store.path())
+            .args(["--target", output.to_str().unwrap(), "--mode", "copy"])
+            .args(args);
+        cmd
+    };
+
+    lash(&["link", package.to_str().unwrap()])
+        .assert()
+        .success();
+    assert!(out_file.is_file() && !out_file.is_symlink());
+
+    lash(&["store", "export", export_file.to_str().unwrap()])
+        .assert()
+        .success();
+    lash(&["store", "forget", package.to_str().unwrap()])
+        .assert()
+        .success();
+    lash(&["store", "import", export_file.to_str().unwrap()])
+        .assert()
+        .success();
+
+    // The hash survived so the copy is still known to be unchanged
+    lash(&["store", "list"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("copy"));
+    lash(&["status", package.to_str().unwrap()])
+        .assert()
+        .success();
+    out_file.write_str("changed").unwrap();
+    lash(&["status", package.to_str().unwrap()])
+        .assert()
+        .failure()
+        .stdout(predicates::str::contains("modified"));
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
+
+#[test]
+fn export_import_non_utf8() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let name = OsStr::from_bytes(b"a\xff");
+
+    std::fs::write(package.path().join(name), "a").unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(package.path())
+            .env("LASH_STORE", store.path())
+            .args(["--target", output.to_str().unwrap()])
+            .args(args);
+        cmd
+    };
+
+    lash(&["link", package.to_str().unwrap()])
+        .assert()
+        .success();
+
+    for format in ["json", "toml"] {
+        let export_file = store.child(format!("export.{}", format));
+        lash(&["store", "export", export_file.to_str().unwrap()])
+            .assert()
+            .success();
+        lash(&["store", "forget", package.to_str().unwrap()])
+            .assert()
+            .success();
+        lash(&["store", "list"]).assert().success().stdout("");
+        lash(&["store", "import", export_file.to_str().unwrap()])
+            .assert()
+            .success();
+
+        let loaded = Store::load(store.child("store.bin").path()).unwrap();
+        let entry = &loaded.entries[&output.path().join(name)];
+        assert_eq!(entry.source, package.path().join(name));
+    }
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
 }
@@ -0,0 +1,79 @@
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use predicates::str::contains;
+
+#[test]
+fn subtree_targets() {
+    let root = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let home = root.child("home");
+    let package = root.child("packages/nvim");
+
+    package.child("Config/init.lua").write_str("a\n").unwrap();
+    package
+        .child("Config/lua/plugins.lua")
+        .write_str("b\n")
+        .unwrap();
+    package.child("bin/nvim-wrapper").write_str("c\n").unwrap();
+    package.child("notes").write_str("d\n").unwrap();
+    package
+        .child("lash.toml")
+        .write_str(&format!(
+            "[targets]\nConfig = \"{}\"\nbin = \"../../home/.local/bin\"\n",
+            home.child(".config/nvim").display()
+        ))
+        .unwrap();
+    home.child(".config").create_dir_all().unwrap();
+    home.child(".local/bin").create_dir_all().unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(root.path())
+            .env("LASH_STORE", store.path())
+            .args(["--target", home.to_str().unwrap()])
+            .args(args);
+        cmd
+    };
+
+    lash(&["link", "packages/nvim"]).assert().success();
+
+    assert!(home.child(".config/nvim").is_dir());
+    assert!(home.child(".config/nvim/init.lua").is_symlink());
+    assert!(home.child(".config/nvim/lua/plugins.lua").is_symlink());
+    assert!(home.child(".local/bin/nvim-wrapper").is_symlink());
+    assert!(home.child("notes").is_symlink());
+    assert!(!home.child("Config").exists());
+    assert!(!home.child("bin").exists());
+
+    lash(&["status", "packages/nvim"])
+        .assert()
+        .success()
+        .stdout(contains("7 linked"));
+
+    // Removed files are cleaned up from the subtree targets
+    std::fs::remove_file(package.child("Config/lua/plugins.lua")).unwrap();
+    lash(&["relink", "packages/nvim"]).assert().success();
+    assert!(!home.child(".config/nvim/lua/plugins.lua").is_symlink());
+    assert!(home.child(".config/nvim/init.lua").is_symlink());
+
+    // The store can be rebuilt from links outside of the package's target
+    lash(&["store", "forget", "packages/nvim"])
+        .assert()
+        .success();
+    lash(&["store", "rebuild", "packages/nvim"])
+        .assert()
+        .success();
+    lash(&["store", "list"])
+        .assert()
+        .success()
+        .stdout(contains(".config/nvim/init.lua"))
+        .stdout(contains(".local/bin/nvim-wrapper"));
+
+    lash(&["unlink", "packages/nvim"]).assert().success();
+    assert!(!home.child(".config/nvim").exists());
+    assert!(!home.child(".local/bin/nvim-wrapper").exists());
+    assert!(!home.child("notes").exists());
+
+    root.close().unwrap();
+    store.close().unwrap();
+}
@@ -0,0 +1,171 @@
+use std::fs;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+
+#[test]
+fn undo_link() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let in_file = package.child("sub_dir/file.txt");
+    let out_dir = output.child("sub_dir");
+    let out_file = output.child("sub_dir/file.txt");
+
+    in_file.touch().unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(package.path())
+            .env("LASH_STORE", store.path())
+            .args(args);
+        cmd
+    };
+
+    lash(&[
+        "--target",
+        output.to_str().unwrap(),
+        "link",
+        package.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    assert!(out_file.is_symlink(), "Out file is not a symlink");
+
+    lash(&["undo"]).assert().success();
+
+    assert!(!out_file.is_symlink(), "Link wasn't removed");
+    assert!(!out_dir.exists(), "Created directory wasn't removed");
+    assert!(in_file.exists(), "In file was removed");
+
+    lash(&["store", "list"]).assert().success().stdout("");
+
+    // Nothing left to undo
+    lash(&["undo"]).assert().failure();
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
+
+#[test]
+fn undo_adopt() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let in_file = package.child("file.txt");
+    let out_file = output.child("file.txt");
+    let in_contents: Vec<u8> = rand::random_iter().take(256).collect();
+    let out_contents: Vec<u8> = rand::random_iter().take(256).collect();
+
+    in_file.write_binary(&in_contents).unwrap();
+    out_file.write_binary(&out_contents).unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(package.path())
+            .env("LASH_STORE", store.path())
+            .args(args);
+        cmd
+    };
+
+    lash(&[
+        "--target",
+        output.to_str().unwrap(),
+        "link",
+        "--adopt",
+        package.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    assert!(out_file.is_symlink(), "Out file is not a symlink");
+    assert_eq!(fs::read(in_file.path()).unwrap(), out_contents);
+
+    lash(&["undo"]).assert().success();
+
+    assert!(!out_file.is_symlink(), "Out file is still a symlink");
+    assert_eq!(
+        fs::read(out_file.path()).unwrap(),
+        out_contents,
+        "Out file contents weren't restored"
+    );
+    assert_eq!(
+        fs::read(in_file.path()).unwrap(),
+        in_contents,
+        "In file contents weren't restored"
+    );
+
+    let history = lash(&["history"]).assert().success();
+    let history = String::from_utf8(history.get_output().stdout.clone()).unwrap();
+    let lines: Vec<&str> = history.lines().collect();
+    assert_eq!(lines.len(), 2, "Unexpected history {:?}", history);
+    assert!(lines[0].starts_with("2\t") && lines[0].contains("undoes 1"));
+    assert!(lines[1].starts_with("1\t") && lines[1].contains("undone by 2"));
+
+    // Undoing the undo adopts the file again
+    lash(&["undo", "2"]).assert().success();
+
+    assert!(out_file.is_symlink(), "Out file is not a symlink");
+    assert_eq!(fs::read(in_file.path()).unwrap(), out_contents);
+
+    // The last ID is found from the journal itself if it wasn't kept
+    fs::remove_file(store.child("journal/last_id")).unwrap();
+    lash(&["undo", "3"]).assert().success();
+    let history = lash(&["history", "--limit", "1"]).assert().success();
+    let history = String::from_utf8(history.get_output().stdout.clone()).unwrap();
+    assert!(
+        history.starts_with("4\t"),
+        "Unexpected history {:?}",
+        history
+    );
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
+
+#[test]
+fn undo_keeps_later_edits() {
+    let package = assert_fs::TempDir::new().unwrap();
+    let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
+    let in_file = package.child("file.txt");
+    let out_file = output.child("file.txt");
+
+    in_file.write_str("package\n").unwrap();
+    out_file.write_str("adopted\n").unwrap();
+
+    let lash = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(package.path())
+            .env("LASH_STORE", store.path())
+            .args(args);
+        cmd
+    };
+
+    lash(&[
+        "--target",
+        output.to_str().unwrap(),
+        "link",
+        "--adopt",
+        package.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    // Edited through the link after adopting
+    in_file.write_str("edited\n").unwrap();
+
+    lash(&["undo"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("has changed"));
+
+    in_file.assert("edited\n");
+
+    package.close().unwrap();
+    output.close().unwrap();
+    store.close().unwrap();
+}
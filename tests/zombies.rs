@@ -5,6 +5,7 @@ use assert_fs::prelude::*;
 fn link_removes_zombie() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
     let in_file = package.child("zombie.txt");
     let out_zombie = output.child("zombie.txt");
 
@@ -13,6 +14,7 @@ fn link_removes_zombie() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),
@@ -32,6 +34,7 @@ fn link_removes_zombie() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),
@@ -46,12 +49,14 @@ fn link_removes_zombie() {
 
     package.close().unwrap();
     output.close().unwrap();
+    store.close().unwrap();
 }
 
 #[test]
 fn unlink_removes_zombie() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
     let in_file = package.child("zombie.txt");
     let out_zombie = output.child("zombie.txt");
 
@@ -60,6 +65,7 @@ fn unlink_removes_zombie() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),
@@ -79,6 +85,7 @@ fn unlink_removes_zombie() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),
@@ -93,12 +100,14 @@ fn unlink_removes_zombie() {
 
     package.close().unwrap();
     output.close().unwrap();
+    store.close().unwrap();
 }
 
 #[test]
 fn remove_1_zombie_dir() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
 
     let in_dir = package.child("sub_dir");
     let in_file = in_dir.child("zombie.txt");
@@ -111,6 +120,7 @@ fn remove_1_zombie_dir() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),
@@ -132,6 +142,7 @@ fn remove_1_zombie_dir() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),
@@ -152,6 +163,7 @@ fn remove_1_zombie_dir() {
 fn remove_zombie_nested_dirs() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
 
     let in_dir = package.child("a");
     let in_dir2 = in_dir.child("b");
@@ -170,6 +182,7 @@ fn remove_zombie_nested_dirs() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),
@@ -191,6 +204,7 @@ fn remove_zombie_nested_dirs() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),
@@ -211,6 +225,7 @@ fn remove_zombie_nested_dirs() {
 fn dont_remove_nested_dirs_with_zombies() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
 
     let in_dir = package.child("a");
     let in_file = in_dir.child("a_a.txt");
@@ -233,6 +248,7 @@ fn dont_remove_nested_dirs_with_zombies() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),
@@ -259,6 +275,7 @@ fn dont_remove_nested_dirs_with_zombies() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),
@@ -282,6 +299,7 @@ fn dont_remove_nested_dirs_with_zombies() {
 fn dont_remove_other_empty_dirs() {
     let package = assert_fs::TempDir::new().unwrap();
     let output = assert_fs::TempDir::new().unwrap();
+    let store = assert_fs::TempDir::new().unwrap();
 
     let out_dir = output.child("sub_dir");
     out_dir.create_dir_all().unwrap();
@@ -289,6 +307,7 @@ fn dont_remove_other_empty_dirs() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
         .current_dir(package.path())
+        .env("LASH_STORE", store.path())
         .args([
             "--target",
             output.to_str().unwrap(),